@@ -0,0 +1,62 @@
+//! Cache of discovered repositories, revalidated by the modification times of the searched directories
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Cached searches keyed by the base path and the options that affect discovery
+#[derive(Serialize, Deserialize, Default)]
+struct Cache {
+    searches: HashMap<String, Search>,
+}
+
+/// Result of one search along with the directories it walked
+#[derive(Serialize, Deserialize)]
+struct Search {
+    repos: Vec<PathBuf>,
+    /// Directories outside any repo and their modification times; adding or removing
+    /// anything below the base path changes at least one of them
+    dirs: Vec<(PathBuf, SystemTime)>,
+}
+
+/// Location of the cache file under the user cache directory
+fn cache_file() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("mpr").join("repos.json"))
+}
+
+fn read() -> Cache {
+    cache_file()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the repos found by an earlier search with the same key, unless any walked directory has changed since
+pub fn lookup(key: &str) -> Option<Vec<PathBuf>> {
+    let mut cache = read();
+    let search = cache.searches.remove(key)?;
+    let unchanged = search.dirs.iter().all(|(dir, modified)| mtime(dir).as_ref() == Some(modified));
+    unchanged.then_some(search.repos)
+}
+
+/// Records the result of a search, replacing any earlier one with the same key
+pub fn store(key: &str, repos: Vec<PathBuf>, dirs: Vec<(PathBuf, SystemTime)>) -> io::Result<()> {
+    let path = cache_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+    let mut cache = read();
+    cache.searches.insert(key.to_string(), Search { repos, dirs });
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string(&cache)?)
+}
+
+/// Modification time of a directory, or `None` if it no longer exists
+pub fn mtime(dir: &Path) -> Option<SystemTime> {
+    std::fs::metadata(dir).and_then(|metadata| metadata.modified()).ok()
+}
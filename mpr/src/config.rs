@@ -0,0 +1,63 @@
+//! Loading of `mpr.toml` configuration files
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File name looked up in the base path and the user config directory
+pub const CONFIG_FILE_NAME: &str = "mpr.toml";
+
+/// Color choice as given by `--color` or written in the config file
+#[derive(Deserialize, ValueEnum, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSetting {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Defaults read from `mpr.toml`; every field is optional
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Maximum number of repos processed concurrently
+    pub jobs: Option<usize>,
+    /// Glob patterns of repos to skip, added to any `--exclude` flags
+    pub exclude: Vec<String>,
+    /// Action to run when none is given on the command line
+    pub action: Option<String>,
+    /// Whether to color the output
+    pub color: Option<ColorSetting>,
+    /// Command run when a lockfile is present, replacing the built-in one, e.g. `"Cargo.lock" = "cargo update --workspace"`
+    pub managers: BTreeMap<String, String>,
+}
+
+/// Reads the config at `explicit` if given, otherwise the first of the base path
+/// and user config directory that contains an `mpr.toml`
+pub fn load(base_path: &Path, explicit: Option<&Path>) -> Result<Config, String> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => match candidates(base_path).into_iter().find(|path| path.is_file()) {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        },
+    };
+
+    let contents = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse {:?}: {}", path, e))
+}
+
+/// Locations searched for a config file, in order of precedence
+fn candidates(base_path: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![base_path.join(CONFIG_FILE_NAME)];
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        candidates.push(config_home.join("mpr").join(CONFIG_FILE_NAME));
+    }
+
+    candidates
+}
@@ -1,260 +1,4198 @@
-use clap::{Parser, Subcommand};
-use git2::Repository;
+mod cache;
+mod config;
+mod native;
+mod outdated;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use git2::{BranchType, IndexAddOption, Oid, Repository, RepositoryState, Status, StatusOptions};
+use config::{ColorSetting, Config};
+use native::{PullOptions, PullOutcome};
+use outdated::Report;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::process::Stdio;
+use tokio::process::Command;
 use walkdir::WalkDir;
-use std::io::{self, Write};
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use tokio::sync::mpsc;
+use std::io::{self, IsTerminal, Write};
+use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
+use futures::future::{self, BoxFuture, FutureExt};
 use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::panic::AssertUnwindSafe;
+
+/// Prints an informational line unless `--quiet` was given
+macro_rules! info {
+    ($args:expr, $($arg:tt)*) => {
+        if !$args.is_quiet() {
+            print_line(&format!($($arg)*), false);
+        }
+    };
+}
+
+/// Prints an error line to stderr, held back with the repo's other output under `--sorted`
+macro_rules! error {
+    ($($arg:tt)*) => {
+        print_line(&format!($($arg)*), true)
+    };
+}
+
+/// Progress bar across all repos, set while `process_repositories` is running
+static PROGRESS: OnceLock<ProgressBar> = OnceLock::new();
+
+/// The `--jobs` limit shared by repos and the extra dependency installs they run concurrently
+static JOBS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+
+/// Cancelled by `--fail-fast` once a repo fails, stopping the commands of every other repo
+static CANCEL: OnceLock<CancellationToken> = OnceLock::new();
+
+/// One lock per common git dir, taken around pulls so worktrees of the same repo don't update its shared refs at once
+static WORKTREE_LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+
+/// Facts recorded while processing a repo by helpers that only return success or failure
+#[derive(Default)]
+struct RepoState {
+    /// A command hit `--timeout`
+    timed_out: Cell<bool>,
+    /// A command was stopped or refused because `--fail-fast` cancelled the run
+    cancelled: Cell<bool>,
+    /// This repo's failure is what cancelled the run, so it stays failed rather than cut short
+    failed_fast: Cell<bool>,
+    /// Every failure recorded along the way, in order, to tell why the repo failed
+    errors: RefCell<Vec<RepoError>>,
+    /// Exit status of the first command that exited unsuccessfully
+    exit_code: Cell<Option<i32>>,
+    /// Most attempts any single network operation needed
+    attempts: Cell<u32>,
+    /// Stale remote-tracking refs removed by `--prune`
+    pruned: Cell<usize>,
+    /// Paths a pull left with merge conflicts
+    conflicts: RefCell<Vec<String>>,
+    /// Checked out branch, or short commit hash when detached, shown in output prefixes
+    branch: RefCell<Option<String>>,
+    /// Output held back until the repo finishes, or the run ends under `--sorted`; unset under `--stream`
+    output: RefCell<Option<CapturedOutput>>,
+    /// Enclosing JS workspace root that installs this repo's Node packages, set before processing starts
+    js_workspace: RefCell<Option<PathBuf>>,
+    /// Enclosing Cargo workspace root whose `cargo update` covers this repo, set before processing starts
+    cargo_workspace: RefCell<Option<PathBuf>>,
+}
+
+/// A repo's colored stdout and stderr, kept in the order it was written
+struct CapturedOutput {
+    color: ColorChoice,
+    /// Consecutive writes to the same stream, flagged with whether they go to stderr
+    chunks: Vec<(bool, Buffer)>,
+}
+
+impl CapturedOutput {
+    fn new(color: ColorChoice) -> Self {
+        CapturedOutput { color, chunks: Vec::new() }
+    }
+
+    /// Buffer for the next write to stdout or stderr, starting a new chunk when the stream changes
+    fn target(&mut self, to_stderr: bool) -> &mut Buffer {
+        if self.chunks.last().map(|(stderr, _)| *stderr) != Some(to_stderr) {
+            let writer = if to_stderr { BufferWriter::stderr(self.color) } else { BufferWriter::stdout(self.color) };
+            self.chunks.push((to_stderr, writer.buffer()));
+        }
+        &mut self.chunks.last_mut().expect("Chunk was just pushed").1
+    }
+
+    /// Writes the held back output to the terminal as one block that no other repo's output can split
+    fn print(&self) {
+        static PRINTING: Mutex<()> = Mutex::new(());
+        let _printing = PRINTING.lock().unwrap_or_else(PoisonError::into_inner);
+        suspend_progress(|| {
+            for (to_stderr, buffer) in &self.chunks {
+                let writer = if *to_stderr { BufferWriter::stderr(self.color) } else { BufferWriter::stdout(self.color) };
+                let _ = writer.print(buffer);
+            }
+        });
+    }
+}
+
+tokio::task_local! {
+    /// State of the repo processed by the current task
+    static REPO_STATE: RepoState;
+}
+
+/// Message attached to stash entries created by `--autostash`
+const AUTOSTASH_MESSAGE: &str = "mpr-autostash";
+
+/// File at the base path listing globs of repos to skip, one per line
+const IGNORE_FILE: &str = ".mprignore";
 
 /// Command-line arguments for the script
-#[derive(Parser)]
+#[derive(Parser, Clone)]
+#[clap(after_help = "Bare repos (mirrors) are fetched by pull and update, and skipped by actions that need a working tree.")]
 struct Args {
     #[clap(default_value = ".")]
     path: String,
 
+    /// Shell out to the `git` binary for pulls instead of using libgit2
+    #[clap(long, global = true)]
+    use_git_cli: bool,
+
+    /// Rebase local commits onto the upstream instead of merging when pulling
+    #[clap(long, global = true)]
+    rebase: bool,
+
+    /// Only pull when the branch can be fast-forwarded; diverged repos are reported instead of merged
+    #[clap(long, global = true, conflicts_with = "rebase")]
+    ff_only: bool,
+
+    /// Pull this local branch in every repo instead of the checked out one, switching to it for the pull
+    #[clap(long = "branch", global = true, value_name = "NAME")]
+    pull_branch: Option<String>,
+
+    /// Stay on the `--branch` branch after pulling instead of switching back
+    #[clap(long, global = true, requires = "pull_branch")]
+    stay: bool,
+
+    /// Run `git merge --abort` when a pull stops with merge conflicts; the repo is still reported as conflicted
+    #[clap(long, global = true)]
+    abort_on_conflict: bool,
+
+    /// Pull even when the working tree has uncommitted changes or HEAD is detached
+    #[clap(long, global = true)]
+    force: bool,
+
+    /// Treat untracked files as uncommitted changes when checking for a dirty tree
+    #[clap(long, global = true)]
+    untracked_dirty: bool,
+
+    /// Stash local changes before pulling and restore them afterwards
+    #[clap(long, global = true)]
+    autostash: bool,
+
+    /// Maximum number of repos processed concurrently (defaults to the number of CPUs)
+    #[clap(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Skip repos whose path relative to the base path matches this glob (repeatable); patterns listed in
+    /// a `.mprignore` at the base path are skipped as well
+    #[clap(long, global = true, value_parser = Glob::new)]
+    exclude: Vec<Glob>,
+
+    /// Only process repos whose relative path matches this glob (repeatable, --exclude wins)
+    #[clap(long, global = true, value_parser = Glob::new)]
+    include: Vec<Glob>,
+
+    /// Maximum directory depth to search for repos, measured from the base path (0 is the base path itself)
+    #[clap(long, global = true)]
+    max_depth: Option<usize>,
+
+    /// Only process repos whose `origin` URL (or the `--remote` one) matches this regex, e.g. `github.com/myorg`
+    #[clap(long, global = true, value_name = "REGEX", value_parser = Regex::new)]
+    remote_match: Option<Regex>,
+
+    /// Follow symlinked directories while searching for repos; by default symlinks are not followed.
+    /// Symlink loops are skipped and a repo reachable through several links is processed once
+    #[clap(long, global = true)]
+    follow_links: bool,
+
+    /// Keep searching inside repos for nested repos instead of stopping at each repo root
+    #[clap(long, global = true)]
+    nested: bool,
+
+    /// Reuse the repos found by the previous search of this base path while none of the searched directories changed
+    #[clap(long, global = true)]
+    cache: bool,
+
+    /// Search the base path again even if the cached repo list is still valid, then update the cache
+    #[clap(long, global = true, requires = "cache")]
+    refresh: bool,
+
+    /// Process the repo paths listed one per line in this file, relative to the base path, instead of searching it
+    #[clap(long, global = true, value_name = "FILE")]
+    repos_from: Option<PathBuf>,
+
+    /// Directory names never descended into while searching for repos
+    #[clap(
+        long,
+        global = true,
+        value_delimiter = ',',
+        default_values = [".git", "node_modules", "target", "vendor", ".venv"]
+    )]
+    skip_dirs: Vec<String>,
+
+    /// Set an environment variable for every command run in the repos (repeatable)
+    #[clap(long = "env", global = true, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Load environment variables for every command from a dotenv-style file; `--env` wins on conflicts
+    #[clap(long, global = true, value_name = "FILE")]
+    env_file: Option<PathBuf>,
+
+    /// Print the git and dependency manager commands that would run in each repo without executing them,
+    /// in the same order on every run
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// Only print failures
+    #[clap(short, long, global = true)]
+    quiet: bool,
+
+    /// Print more detail, such as each package the outdated action finds
+    #[clap(short, long, global = true)]
+    verbose: bool,
+
+    /// Output format; `json` prints a single array of per-repo results at the end, `ndjson` a line per
+    /// repo as it starts and finishes
+    #[clap(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Print command output line by line as it arrives instead of one block per repo
+    #[clap(long, global = true)]
+    stream: bool,
+
+    /// Private key tried first when the native pull authenticates against an SSH remote
+    #[clap(long, global = true)]
+    ssh_key: Option<PathBuf>,
+
+    /// Retry pulls and fetches that fail with a network error up to this many times, backing off exponentially
+    #[clap(long, global = true, default_value_t = 0)]
+    retries: u32,
+
+    /// Kill any command still running after this many seconds and report its repo as timed out
+    #[clap(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Pull, fetch and push this remote (e.g. `upstream`) instead of the branch's upstream; repos without it are skipped
+    #[clap(long, global = true)]
+    remote: Option<String>,
+
+    /// Fetch every tag from the remote while pulling or fetching
+    #[clap(long, global = true)]
+    tags: bool,
+
+    /// Remove remote-tracking branches that were deleted on the remote while pulling or fetching
+    #[clap(long, global = true)]
+    prune: bool,
+
+    /// Run `git lfs pull` after a successful pull in repos whose .gitattributes use the LFS filter
+    #[clap(long, global = true)]
+    lfs: bool,
+
+    /// Run `git submodule update --init --recursive` after a successful pull in repos with a .gitmodules
+    #[clap(long, global = true)]
+    submodules: bool,
+
+    /// Hold back each repo's output and print it sorted by path once every repo has finished
+    #[clap(long, global = true, conflicts_with = "stream")]
+    sorted: bool,
+
+    /// Ask before processing each repo; repos then run one at a time until `a` answers yes to all
+    #[clap(long, global = true)]
+    interactive: bool,
+
+    /// Hide the progress bar shown while repos are processed
+    #[clap(long, global = true)]
+    no_progress: bool,
+
+    /// Append a JSON record of every command run (repo, exit status, duration, timestamp) to this file
+    #[clap(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// POST a JSON summary of the run to this URL once every repo has finished
+    #[clap(long, global = true)]
+    webhook: Option<String>,
+
+    /// Post the run summary to this Slack incoming webhook, listing failed repos in red
+    #[clap(long, global = true, value_name = "URL")]
+    slack_webhook: Option<String>,
+
+    /// Write run totals to this file in the Prometheus text format, for the node_exporter textfile collector
+    #[clap(long, global = true, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+
+    /// Show a desktop notification with the success and failure counts when the run ends
+    #[clap(long, global = true)]
+    notify: bool,
+
+    /// List the N repos that took longest in the summary
+    #[clap(long, global = true, value_name = "N")]
+    slowest: Option<usize>,
+
+    /// Stop at the first repo that fails, killing the commands still running in other repos
+    #[clap(long, global = true, conflicts_with = "exit_zero")]
+    fail_fast: bool,
+
+    /// Exit with status 0 even when some repos failed
+    #[clap(long, global = true)]
+    exit_zero: bool,
+
+    /// Read defaults from this file instead of looking for `mpr.toml`
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Ignore any `mpr.toml` config file
+    #[clap(long, global = true, conflicts_with = "config")]
+    no_config: bool,
+
+    /// Pull without installing dependencies; only `update` (the default action) installs them, so with it
+    /// this just makes sure every repo is pulled, and other actions are unaffected
+    #[clap(long, global = true)]
+    no_deps: bool,
+
+    /// With `update` (the default action), rerun the dependency installs without pulling, so nothing is fetched
+    #[clap(long, global = true, conflicts_with = "no_deps")]
+    only_deps: bool,
+
+    /// Install exactly what the lockfiles pin, never rewriting them (e.g. `npm ci`, `cargo fetch --locked`)
+    #[clap(long, visible_alias = "ci", global = true)]
+    frozen: bool,
+
+    /// Install Node dependencies with this tool in every repo with a package.json, whatever lockfiles are present
+    #[clap(long, global = true, value_enum)]
+    manager: Option<NodeManager>,
+
+    /// Pip requirements files installed from each repo root, each with its own `pip install -r`
+    #[clap(long, global = true, value_parser = Glob::new, default_value = "requirements*.txt")]
+    requirements_glob: Glob,
+
+    /// Command used to update Go modules
+    #[clap(long, global = true, value_enum, default_value_t = GoCommand::Tidy)]
+    go_command: GoCommand,
+
+    /// Conda environment to update instead of the `name` in environment.yml
+    #[clap(long, global = true)]
+    conda_env: Option<String>,
+
+    /// Entry point to `deno cache` instead of running `deno install`
+    #[clap(long, global = true)]
+    deno_entry: Option<String>,
+
+    /// When to color the output; `auto` colors only when stdout is a terminal
+    #[clap(long, global = true, value_enum, value_name = "WHEN")]
+    color: Option<ColorSetting>,
+
+    /// Commands run for a lockfile instead of the built-in ones; only settable from the config file
+    #[clap(skip)]
+    managers: BTreeMap<String, String>,
+
     #[clap(subcommand)]
     action: Option<Action>,
 }
 
-/// Subcommands for the script
-#[derive(Subcommand)]
-enum Action {
-    /// Just pull all repos
-    Pull,
-    /// Pull and update dependencies
-    Update,
-}
+impl Args {
+    /// Whether informational output should be suppressed
+    fn is_quiet(&self) -> bool {
+        self.quiet || self.is_json()
+    }
+
+    /// Whether results are emitted as JSON, all at once or as a stream of events, instead of human readable lines
+    fn is_json(&self) -> bool {
+        matches!(self.format, Format::Json | Format::Ndjson)
+    }
+
+    /// How many repos to work on at once: `--jobs`, or one per CPU
+    fn job_count(&self) -> usize {
+        let jobs = self.jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+        jobs.max(1)
+    }
+
+    /// Whether dependencies should be upgraded rather than just installed; no action means update
+    fn is_upgrade(&self) -> bool {
+        matches!(self.action, Some(Action::Update) | None) && !self.frozen
+    }
+
+    /// Color choice for the output streams
+    fn color_choice(&self) -> ColorChoice {
+        match self.color {
+            Some(ColorSetting::Auto) | None if io::stdout().is_terminal() => ColorChoice::Auto,
+            Some(ColorSetting::Auto) | None => ColorChoice::Never,
+            Some(ColorSetting::Always) => ColorChoice::Always,
+            Some(ColorSetting::Never) => ColorChoice::Never,
+        }
+    }
+
+    /// Fills in anything not given on the command line from the config file
+    fn apply_config(&mut self, config: Config) -> Result<(), String> {
+        self.jobs = self.jobs.or(config.jobs);
+        self.color = self.color.or(config.color);
+
+        for (lockfile, template) in &config.managers {
+            if template.split_whitespace().next().is_none() {
+                return Err(format!("Empty command configured for {:?} in [managers]", lockfile));
+            }
+        }
+        self.managers = config.managers;
+
+        for pattern in config.exclude {
+            let glob = Glob::new(&pattern).map_err(|e| format!("Invalid exclude pattern {:?}: {}", pattern, e))?;
+            self.exclude.push(glob);
+        }
+
+        if self.action.is_none() {
+            self.action = match config.action.as_deref() {
+                None => None,
+                Some("pull") => Some(Action::Pull),
+                Some("update") => Some(Action::Update),
+                Some("status") => Some(Action::Status),
+                Some("dirty") => Some(Action::Dirty),
+                Some("push") => Some(Action::Push),
+                Some("fetch") => Some(Action::Fetch),
+                Some("audit") => Some(Action::Audit),
+                Some("outdated") => Some(Action::Outdated),
+                Some(other) => return Err(format!("Unsupported default action {:?} in config", other)),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Output formats for the run results
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+    Ndjson,
+}
+
+/// Node package managers that `--manager` can force
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum NodeManager {
+    Npm,
+    Yarn,
+    Pnpm,
+    Bun,
+}
+
+impl NodeManager {
+    /// Name of the tool's executable
+    fn name(self) -> &'static str {
+        match self {
+            NodeManager::Npm => "npm",
+            NodeManager::Yarn => "yarn",
+            NodeManager::Pnpm => "pnpm",
+            NodeManager::Bun => "bun",
+        }
+    }
+}
+
+/// How Go modules are brought up to date
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum GoCommand {
+    /// `go mod tidy`
+    Tidy,
+    /// `go get -u ./...`
+    Get,
+}
+
+/// Subcommands for the script
+#[derive(Subcommand, Clone)]
+enum Action {
+    /// Just pull all repos; bare repos (mirrors) are fetched instead
+    Pull,
+    /// Pull and update dependencies; bare repos (mirrors) are only fetched
+    Update,
+    /// Show branch, dirty state and ahead/behind counts without touching anything
+    Status,
+    /// List the most recent tags of every repo, newest first
+    Tags {
+        /// Number of tags to list per repo
+        #[clap(short = 'n', long, default_value_t = 5)]
+        count: usize,
+    },
+    /// List only the repos with uncommitted changes and how many files are staged, modified or untracked
+    Dirty,
+    /// Push repos that have commits ahead of their upstream
+    Push,
+    /// Fetch all remotes without touching the working tree
+    Fetch,
+    /// Scan dependencies for known vulnerabilities with each ecosystem's audit tool
+    Audit,
+    /// Report how many dependencies are behind their latest release without changing anything
+    Outdated,
+    /// Stage every change and commit it with `message`; repos without changes are skipped
+    Commit {
+        message: String,
+        /// Push the new commit afterwards
+        #[clap(long)]
+        push: bool,
+    },
+    /// Run an arbitrary command in every repo
+    Exec {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Switch every repo that has the branch, locally or on a remote, to it
+    Checkout {
+        branch: String,
+    },
+    /// Create and switch to a new branch in every repo, from `base` or the current HEAD
+    Branch {
+        name: String,
+        base: Option<String>,
+        /// Push the new branch and set its upstream
+        #[clap(long)]
+        push_new: bool,
+    },
+    /// Run `git gc` in every repo and report the space it reclaimed
+    Gc {
+        /// Pass `--aggressive` to `git gc`, which is much slower but packs more tightly
+        #[clap(long)]
+        aggressive: bool,
+    },
+    /// Hard reset every repo to its upstream, listing the commits and changes it discards first
+    Reset {
+        /// Confirm discarding local commits and changes; without it only `--dry-run` is allowed
+        #[clap(long)]
+        yes: bool,
+        /// Also delete untracked files and directories with `git clean -fd`
+        #[clap(long)]
+        clean: bool,
+    },
+    /// Clone repos listed in a manifest of `url [dest]` lines
+    Clone {
+        manifest: PathBuf,
+    },
+    /// Print a shell completion script to stdout
+    #[clap(hide = true)]
+    Completions {
+        shell: Shell,
+    },
+}
+
+
+impl Action {
+    /// Name of the action as used on the command line
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Pull => "pull",
+            Action::Update => "update",
+            Action::Status => "status",
+            Action::Dirty => "dirty",
+            Action::Tags { .. } => "tags",
+            Action::Push => "push",
+            Action::Fetch => "fetch",
+            Action::Audit => "audit",
+            Action::Outdated => "outdated",
+            Action::Commit { .. } => "commit",
+            Action::Exec { .. } => "exec",
+            Action::Checkout { .. } => "checkout",
+            Action::Branch { .. } => "branch",
+            Action::Gc { .. } => "gc",
+            Action::Reset { .. } => "reset",
+            Action::Clone { .. } => "clone",
+            Action::Completions { .. } => "completions",
+        }
+    }
+}
+
+/// Why a command run through `run_command` did not succeed
+enum CommandError {
+    /// The executable is not installed or not on the PATH
+    NotFound,
+    /// The command could not be started, or exited unsuccessfully; holds what it wrote to stderr
+    Failed(String),
+    /// The command was killed after running longer than `--timeout`
+    TimedOut,
+    /// The command was killed, or never started, because `--fail-fast` cancelled the run
+    Cancelled,
+}
+
+/// Why a repo failed, so the summary can group failures by cause
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum RepoError {
+    /// A command exited unsuccessfully
+    CommandFailed,
+    /// A required tool is not installed or not on the PATH
+    MissingTool,
+    /// A command could not be started or waited on
+    Io,
+    /// A command ran longer than `--timeout`
+    TimedOut,
+    /// Opening the repository or a libgit2 operation failed
+    Git,
+    /// Processing the repo panicked; the other repos carried on
+    Panicked,
+}
+
+impl RepoError {
+    fn label(self) -> &'static str {
+        match self {
+            RepoError::CommandFailed => "command failed",
+            RepoError::MissingTool => "missing tool",
+            RepoError::Io => "I/O error",
+            RepoError::TimedOut => "timed out",
+            RepoError::Git => "git error",
+            RepoError::Panicked => "panicked",
+        }
+    }
+
+    /// The error that best explains a failed repo: a missing tool is often tolerated,
+    /// so any other recorded failure takes precedence over it
+    fn cause(errors: &[RepoError]) -> Option<RepoError> {
+        errors
+            .iter()
+            .copied()
+            .find(|error| *error != RepoError::MissingTool)
+            .or_else(|| errors.first().copied())
+    }
+}
+
+/// Notes a failure in the current repo; the repo's outcome still decides whether it counts
+fn record_error(error: RepoError) {
+    let _ = REPO_STATE.try_with(|state| state.errors.borrow_mut().push(error));
+}
+
+/// What happened to a repository during the run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Succeeded,
+    Failed,
+    Skipped,
+    #[serde(rename = "timed_out")]
+    TimedOut,
+    /// The branch has diverged from its upstream and was left as it was, under `--ff-only` or by the native pull
+    Diverged,
+    /// A merging pull stopped with conflicts
+    Conflicted,
+}
+
+impl Outcome {
+    /// Whether the repository should count towards a non-zero exit status
+    fn is_failure(self) -> bool {
+        matches!(self, Outcome::Failed | Outcome::TimedOut | Outcome::Diverged | Outcome::Conflicted)
+    }
+
+    /// Combines the outcomes of two steps run against the same repository
+    fn and(self, other: Outcome) -> Outcome {
+        match (self, other) {
+            (Outcome::TimedOut, _) | (_, Outcome::TimedOut) => Outcome::TimedOut,
+            (Outcome::Conflicted, _) | (_, Outcome::Conflicted) => Outcome::Conflicted,
+            (Outcome::Failed, _) | (_, Outcome::Failed) => Outcome::Failed,
+            (Outcome::Diverged, _) | (_, Outcome::Diverged) => Outcome::Diverged,
+            (Outcome::Skipped, Outcome::Skipped) => Outcome::Skipped,
+            _ => Outcome::Succeeded,
+        }
+    }
+}
+
+impl From<bool> for Outcome {
+    fn from(success: bool) -> Self {
+        if success {
+            Outcome::Succeeded
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+/// Result of processing a single repository
+#[derive(Serialize)]
+struct RepoResult {
+    path: PathBuf,
+    action: &'static str,
+    outcome: Outcome,
+    /// Exit status of the first command that failed, 0 on success, or 1 when the failure wasn't a command exiting
+    exit_code: i32,
+    duration_secs: f64,
+    ahead: Option<usize>,
+    behind: Option<usize>,
+    /// Attempts taken by the most retried network operation, 0 if none ran
+    attempts: u32,
+    /// Stale remote-tracking refs removed by `--prune`
+    pruned: usize,
+    /// Outcome of the `--submodules` update, if one ran
+    submodules: Option<Outcome>,
+    /// Paths left with merge conflicts by the pull
+    conflicts: Vec<String>,
+    /// Main repo this linked worktree belongs to
+    worktree_of: Option<PathBuf>,
+    /// The `--lfs` pull, if one ran
+    lfs: Option<LfsPull>,
+    /// Cause of the failure, for repos that failed
+    error: Option<RepoError>,
+}
+
+/// Message from a repo's task to the collector in `process_repositories`
+enum RepoMessage {
+    /// The repo got a job slot and processing began
+    Started(PathBuf),
+    /// The repo is done, with its output if it was held back under `--sorted`
+    Finished(Box<RepoResult>, Option<CapturedOutput>),
+}
+
+/// A line of `--format ndjson` output
+#[derive(Serialize)]
+#[serde(tag = "phase", rename_all = "lowercase")]
+enum Event<'a> {
+    Start {
+        path: &'a Path,
+        action: &'static str,
+    },
+    Finish {
+        #[serde(flatten)]
+        result: &'a RepoResult,
+    },
+}
+
+impl Event<'_> {
+    /// Writes the event as one line, flushed right away so a reader sees it as it happens
+    fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => suspend_progress(|| println!("{}", line)),
+            Err(e) => error!("Failed to serialize event: {}", e),
+        }
+    }
+}
+
+/// Result of running `git lfs pull` in a repository
+#[derive(Serialize, Clone, Copy)]
+struct LfsPull {
+    outcome: Outcome,
+    /// Growth of the local LFS object store during the pull
+    bytes: u64,
+    duration_secs: f64,
+}
+
+impl RepoResult {
+    /// Result for a repo that was never processed, having been declined at the `--interactive` prompt
+    /// or cancelled by `--fail-fast` before it started
+    fn skipped(relative_path: &Path, args: &Args) -> Self {
+        RepoResult {
+            path: relative_path.to_path_buf(),
+            action: args.action.as_ref().map_or("update", Action::name),
+            outcome: Outcome::Skipped,
+            exit_code: 0,
+            duration_secs: 0.0,
+            ahead: None,
+            behind: None,
+            attempts: 0,
+            pruned: 0,
+            submodules: None,
+            conflicts: Vec::new(),
+            worktree_of: None,
+            lfs: None,
+            error: None,
+        }
+    }
+
+    /// Result for a repo whose processing panicked
+    fn panicked(relative_path: &Path, args: &Args) -> Self {
+        RepoResult {
+            outcome: Outcome::Failed,
+            exit_code: 1,
+            error: Some(RepoError::Panicked),
+            ..RepoResult::skipped(relative_path, args)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = Args::parse();
+
+    if let Some(Action::Completions { shell }) = args.action {
+        clap_complete::generate(shell, &mut Args::command(), "mpr", &mut io::stdout());
+        return;
+    }
+
+    if !args.no_config {
+        let loaded = config::load(Path::new(&args.path), args.config.as_deref())
+            .and_then(|config| args.apply_config(config));
+        if let Err(e) = loaded {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    if let Some(env_file) = &args.env_file {
+        match load_env_file(env_file) {
+            // File values go first so that later `--env` flags override them
+            Ok(mut vars) => {
+                vars.append(&mut args.env);
+                args.env = vars;
+            }
+            Err(e) => {
+                error!("Failed to read env file {:?}: {}", env_file, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if let Some(Action::Reset { yes: false, .. }) = args.action {
+        if !args.dry_run {
+            error!("reset discards local commits and changes in every repo; pass --yes to confirm, or --dry-run to preview");
+            std::process::exit(2);
+        }
+    }
+
+    // Every other action ignores `--only-deps`, so `pull --only-deps` would quietly pull instead
+    if let Some(action) = args.action.as_ref().filter(|action| args.only_deps && !matches!(action, Action::Update)) {
+        error!("--only-deps only applies to update (the default action), not {}", action.name());
+        std::process::exit(2);
+    }
+
+    // A dry run is a plan to read or diff, so list the repos in path order rather than as they finish
+    if args.dry_run && !args.stream {
+        args.sorted = true;
+    }
+
+    if let Some(log_file) = &args.log_file {
+        if let Err(e) = init_log_file(log_file) {
+            error!("Failed to open log file {:?}: {}", log_file, e);
+            std::process::exit(2);
+        }
+    }
+
+    info!(args, "MetaZeta");
+    let base_path = Path::new(&args.path);
+    let started = Instant::now();
+
+
+    let success = match &args.action {
+        Some(Action::Clone { manifest }) => clone_repositories(base_path, manifest, &args).await,
+        _ => {
+            let results = process_repositories(base_path, &args).await;
+            let summary = RunSummary::new(&results, started.elapsed());
+            if let Some(url) = &args.webhook {
+                send_webhook(url, &summary).await;
+            }
+            if let Some(url) = &args.slack_webhook {
+                send_slack(url, &summary).await;
+            }
+            if let Some(path) = &args.metrics_file {
+                if let Err(e) = write_metrics(path, &summary) {
+                    error!("Failed to write metrics to {:?}: {}", path, e);
+                }
+            }
+            if args.notify {
+                notify_desktop(&summary);
+            }
+            results.iter().all(|result| !result.outcome.is_failure())
+        }
+    };
+
+    // Exit explicitly, since returning would wait on any native pull thread abandoned at `--timeout`
+    std::process::exit(if !success && !args.exit_zero { 1 } else { 0 });
+}
+
+/// Totals for a whole run, as sent to `--webhook`
+#[derive(Serialize)]
+struct RunSummary {
+    total: usize,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    duration_secs: f64,
+    failed_repos: Vec<PathBuf>,
+}
+
+impl RunSummary {
+    fn new(results: &[RepoResult], duration: Duration) -> Self {
+        let count = |outcome: Outcome| results.iter().filter(|result| result.outcome == outcome).count();
+        let mut failed_repos: Vec<PathBuf> = results
+            .iter()
+            .filter(|result| result.outcome.is_failure())
+            .map(|result| result.path.clone())
+            .collect();
+        failed_repos.sort();
+
+        RunSummary {
+            total: results.len(),
+            succeeded: count(Outcome::Succeeded),
+            failed: failed_repos.len(),
+            skipped: count(Outcome::Skipped),
+            duration_secs: duration.as_secs_f64(),
+            failed_repos,
+        }
+    }
+}
+
+/// POSTs the run summary; a failed delivery is reported but never fails the run
+async fn send_webhook(url: &str, summary: &RunSummary) {
+    let sent = reqwest::Client::new()
+        .post(url)
+        .json(summary)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+
+    if let Err(e) = sent {
+        error!("Failed to send webhook to {}: {}", url, e);
+    }
+}
+
+/// Posts the run summary as a Slack Block Kit message; like `--webhook`, a failed delivery never fails the run
+async fn send_slack(url: &str, summary: &RunSummary) {
+    let totals = format!(
+        "*{}* succeeded, *{}* failed, *{}* skipped in {:.0}s",
+        summary.succeeded, summary.failed, summary.skipped, summary.duration_secs
+    );
+    let mut message = serde_json::json!({
+        "text": format!("mpr finished {} repos: {}", summary.total, totals.replace('*', "")),
+        "blocks": [
+            {
+                "type": "header",
+                "text": { "type": "plain_text", "text": format!("mpr finished {} repos", summary.total) }
+            },
+            {
+                "type": "section",
+                "text": { "type": "mrkdwn", "text": totals }
+            }
+        ]
+    });
+
+    // Blocks have no color of their own, so failures go in an attachment with a red bar
+    if !summary.failed_repos.is_empty() {
+        let repos: Vec<String> = summary
+            .failed_repos
+            .iter()
+            .map(|path| format!("• `{}`", path.display()))
+            .collect();
+        // Slack rejects section text longer than 3000 characters
+        let mut text = format!("*Failed repos*\n{}", repos.join("\n"));
+        if text.chars().count() > 3000 {
+            // Cut after the last whole line within the limit; the bullets are multi-byte, so count characters
+            let limit = text.char_indices().nth(2990).map_or(text.len(), |(index, _)| index);
+            let cut = text[..limit].rfind('\n').unwrap_or(0);
+            text.truncate(cut);
+            text.push_str("\n…");
+        }
+        message["attachments"] = serde_json::json!([{
+            "color": "#d32f2f",
+            "blocks": [{ "type": "section", "text": { "type": "mrkdwn", "text": text } }]
+        }]);
+    }
+
+    let sent = reqwest::Client::new()
+        .post(url)
+        .json(&message)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+
+    if let Err(e) = sent {
+        error!("Failed to post summary to Slack: {}", e);
+    }
+}
+
+/// Writes the run totals as Prometheus metrics, replacing the file atomically so the collector never reads half of it
+fn write_metrics(path: &Path, summary: &RunSummary) -> io::Result<()> {
+    let finished = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let metrics = [
+        ("mpr_repos_total", "Repos processed in the last run", summary.total as f64),
+        ("mpr_repos_succeeded", "Repos that succeeded in the last run", summary.succeeded as f64),
+        ("mpr_repos_failed", "Repos that failed in the last run", summary.failed as f64),
+        ("mpr_repos_skipped", "Repos skipped in the last run", summary.skipped as f64),
+        ("mpr_run_duration_seconds", "Wall-clock duration of the last run", summary.duration_secs),
+        ("mpr_last_run_timestamp_seconds", "Unix time the last run finished", finished as f64),
+    ];
+
+    let mut contents = String::new();
+    for (name, help, value) in metrics {
+        contents.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"));
+    }
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path)
+}
+
+/// Shows the run totals as a desktop notification; does nothing where there is no desktop to notify
+fn notify_desktop(summary: &RunSummary) {
+    let body = format!(
+        "{} succeeded, {} failed, {} skipped in {:.0}s",
+        summary.succeeded, summary.failed, summary.skipped, summary.duration_secs
+    );
+    let shown = notify_rust::Notification::new()
+        .summary(&format!("mpr finished {} repos", summary.total))
+        .body(&body)
+        .show();
+
+    // Headless machines have no notification service, which is expected rather than an error
+    if let Err(e) = shown {
+        tracing::debug!(error = %e, "desktop notification not shown");
+    }
+}
+
+/// Clones every manifest entry that is not already present under the base path
+async fn clone_repositories(base_path: &Path, manifest: &Path, args: &Args) -> bool {
+    let contents = match std::fs::read_to_string(manifest) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Failed to read manifest {:?}: {}", manifest, e);
+            return false;
+        }
+    };
+
+    let entries: Vec<(String, PathBuf)> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let url = parts.next()?.to_string();
+            let dest = match parts.next() {
+                Some(dest) => PathBuf::from(dest),
+                None => PathBuf::from(repo_name_from_url(&url)?),
+            };
+            Some((url, dest))
+        })
+        .collect();
+
+    let results: Vec<bool> = stream::iter(entries)
+        .map(|(url, dest)| async move {
+            let target = base_path.join(&dest);
+            if open_git_repo(&target).is_some() {
+                info!(args, "Repository {:?} already exists, skipping", dest);
+                return true;
+            }
+
+            info!(args, "Cloning {} into {:?}", url, dest);
+            let target = target.to_string_lossy();
+            run_command(base_path, "git", &["clone", &url, &target], "Git", &dest, args).await.is_ok()
+        })
+        .buffer_unordered(args.job_count())
+        .collect()
+        .await;
+
+    results.into_iter().all(|cloned| cloned)
+}
+
+/// Sends `tracing` records to `path` as JSON lines, leaving terminal output untouched
+fn init_log_file(path: &Path) -> io::Result<()> {
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    tracing_subscriber::fmt()
+        .json()
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .init();
+    Ok(())
+}
+
+/// Derives the default checkout directory name from a clone URL
+fn repo_name_from_url(url: &str) -> Option<&str> {
+    let name = url.trim_end_matches('/').rsplit(['/', ':']).next()?;
+    let name = name.strip_suffix(".git").unwrap_or(name);
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+
+async fn process_repositories(base_path: &Path, args: &Args) -> Vec<RepoResult> {
+    // Unbounded so a task never waits on the collector, which only starts reading once every repo is spawned;
+    // `--interactive` awaits each task before the next prompt, so a bounded channel would deadlock it
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut args = args.clone();
+    args.exclude.extend(read_ignore_file(base_path));
+    let args = Arc::new(args);
+
+    let semaphore = Arc::new(Semaphore::new(args.job_count()));
+    let _ = JOBS.set(Arc::clone(&semaphore));
+    let cancel = CANCEL.get_or_init(CancellationToken::new);
+
+    let mut repos = match &args.repos_from {
+        Some(list) => read_repository_list(list, base_path),
+        None => discover_repositories(base_path, &args),
+    };
+    if matches!(args.action, Some(Action::Fetch | Action::Gc { .. })) {
+        // Worktrees share the refs and objects of their main repo, so fetching or collecting
+        // garbage in one of them covers them all
+        let mut seen = HashSet::new();
+        repos.retain(|(_, _, repo)| seen.insert(common_dir(repo)));
+    }
+    if let Some(pattern) = &args.remote_match {
+        let remote = args.remote.as_deref().unwrap_or("origin");
+        repos.retain(|(_, _, repo)| remote_matches(repo, remote, pattern));
+    }
+
+    // Symlinks and repeated `--repos-from` entries can lead to one repo by several paths; two
+    // operations running on it at once could corrupt its index, so each real root is processed once
+    let mut roots = HashSet::new();
+    repos.retain_mut(|(path, relative_path, _)| {
+        *path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let first = roots.insert(path.clone());
+        if !first {
+            info!(args, "Skipping {:?}, the same repo was already found at another path", relative_path);
+        }
+        first
+    });
+
+    // Packages of a JS or Cargo workspace get their dependencies from the install at the workspace root
+    let workspace_roots = |is_root: fn(&Path) -> bool| -> Vec<PathBuf> {
+        repos.iter().map(|(path, _, _)| path).filter(|path| is_root(path)).cloned().collect()
+    };
+    let js_roots = workspace_roots(is_js_workspace_root);
+    let cargo_roots = workspace_roots(is_cargo_workspace_root);
+    let enclosing = |roots: &[PathBuf], path: &Path, is_member: fn(&Path, &Path) -> bool| {
+        roots.iter().find(|root| path != *root && path.starts_with(root) && is_member(root, path)).cloned()
+    };
+
+    let progress = if args.is_quiet() || args.no_progress {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(repos.len() as u64)
+    };
+    progress.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} repos processed").unwrap());
+    let _ = PROGRESS.set(progress.clone());
+
+    let mut prompting = args.interactive;
+    let mut declined = Vec::new();
+    for (path, relative_path, repo) in repos {
+        if prompting {
+            match confirm(&repo, &relative_path, &args).await {
+                Answer::Yes => {}
+                Answer::No => {
+                    declined.push(RepoResult::skipped(&relative_path, &args));
+                    continue;
+                }
+                Answer::All => prompting = false,
+                Answer::Quit => break,
+            }
+        }
+
+        let js_workspace = enclosing(&js_roots, &path, is_js_workspace_member);
+        let cargo_workspace = enclosing(&cargo_roots, &path, is_cargo_workspace_member);
+        let tx = tx.clone();
+        let args = Arc::clone(&args);
+        let semaphore = Arc::clone(&semaphore);
+        let task = tokio::spawn(async move {
+            let _permit = semaphore.acquire().await.expect("Semaphore closed");
+            if cancel.is_cancelled() {
+                let _ = tx.send(RepoMessage::Finished(Box::new(RepoResult::skipped(&relative_path, &args)), None));
+                return;
+            }
+            let _ = tx.send(RepoMessage::Started(relative_path.clone()));
+            let state = RepoState {
+                output: RefCell::new((!args.stream).then(|| CapturedOutput::new(args.color_choice()))),
+                js_workspace: RefCell::new(js_workspace),
+                cargo_workspace: RefCell::new(cargo_workspace),
+                ..RepoState::default()
+            };
+            // A panic while processing one repo is reported as its failure rather than ending the run
+            let result = REPO_STATE
+                .scope(state, async {
+                    let processed = AssertUnwindSafe(process_repository(&path, repo, &args, &relative_path)).catch_unwind().await;
+                    REPO_STATE.with(|state| {
+                        let Ok(mut result) = processed else {
+                            error!("Processing {:?} panicked, carrying on with the other repos", relative_path);
+                            return (RepoResult::panicked(&relative_path, &args), state.output.take());
+                        };
+                        if state.timed_out.get() {
+                            result.outcome = Outcome::TimedOut;
+                        }
+                        // Only the repo that failed first counts as failed; the ones it stopped were cut short
+                        if state.cancelled.get() && !state.failed_fast.get() {
+                            result.outcome = Outcome::Skipped;
+                        }
+                        result.exit_code =
+                            if result.outcome.is_failure() { state.exit_code.get().unwrap_or(1) } else { 0 };
+                        result.attempts = state.attempts.get();
+                        result.pruned = state.pruned.get();
+                        result.conflicts = state.conflicts.take();
+                        if result.outcome.is_failure() {
+                            result.error = RepoError::cause(&state.errors.borrow());
+                        }
+                        (result, state.output.take())
+                    })
+                })
+                .await;
+            fail_fast(result.0.outcome, &relative_path, &args);
+            let (result, mut output) = result;
+            // Each repo's output comes out in one block as soon as it finishes, unless `--sorted` holds it for the end
+            if !args.sorted {
+                if let Some(output) = output.take() {
+                    output.print();
+                }
+            }
+            if tx.send(RepoMessage::Finished(Box::new(result), output)).is_err() {
+                error!("Result for {:?} was dropped because the run is shutting down", relative_path);
+            }
+        });
+        // Finish this repo before the next prompt so its output doesn't run into the question
+        if prompting {
+            let _ = task.await;
+        }
+    }
+
+    drop(tx);
+
+    let streaming = args.format == Format::Ndjson;
+    let action = args.action.as_ref().map_or("update", Action::name);
+    let mut finished: Vec<_> = declined.into_iter().map(|result| (result, None)).collect();
+    if streaming {
+        for (result, _) in &finished {
+            Event::Finish { result }.emit();
+        }
+    }
+    while let Some(message) = rx.recv().await {
+        match message {
+            RepoMessage::Started(path) => {
+                if streaming {
+                    Event::Start { path: &path, action }.emit();
+                }
+            }
+            RepoMessage::Finished(result, output) => {
+                if streaming {
+                    Event::Finish { result: &result }.emit();
+                }
+                finished.push((*result, output));
+                progress.inc(1);
+            }
+        }
+    }
+    progress.finish_and_clear();
+
+    if args.sorted {
+        finished.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+    }
+    let results: Vec<RepoResult> = finished
+        .into_iter()
+        .map(|(result, output)| {
+            if let Some(output) = output {
+                output.print();
+            }
+            result
+        })
+        .collect();
+
+    match args.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&results).expect("Failed to serialize results")),
+        Format::Ndjson => {}
+        Format::Text => print_summary(&results, &args),
+    }
+
+    results
+}
+
+/// Answer to the `--interactive` prompt
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Asks on stderr whether to process a repo, showing whether its working tree is dirty
+async fn confirm(repo: &Repository, relative_path: &Path, args: &Args) -> Answer {
+    let state = if is_dirty(repo, args.untracked_dirty) { "dirty" } else { "clean" };
+    let action = args.action.as_ref().map_or("update", Action::name);
+    let mut verb = action.to_string();
+    verb[..1].make_ascii_uppercase();
+
+    loop {
+        suspend_progress(|| {
+            eprint!("{} {} ({})? [y/N/a/q] ", verb, relative_path.display(), state);
+            let _ = io::stderr().flush();
+        });
+
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).map(|read| (read > 0).then_some(line))
+        })
+        .await
+        .expect("Prompt task panicked");
+
+        // End of input behaves like `q` so a closed stdin can't silently decline every repo
+        match line.ok().flatten().as_deref().map(str::trim) {
+            None => return Answer::Quit,
+            Some("y" | "Y" | "yes") => return Answer::Yes,
+            Some("" | "n" | "N" | "no") => return Answer::No,
+            Some("a" | "A" | "all") => return Answer::All,
+            Some("q" | "Q" | "quit") => return Answer::Quit,
+            Some(_) => continue,
+        }
+    }
+}
+
+/// Walks the base path and returns the full and relative paths of every repo that passes the filters
+fn discover_repositories(base_path: &Path, args: &Args) -> Vec<(PathBuf, PathBuf, Repository)> {
+    let excludes = build_globset(&args.exclude);
+    let includes = build_globset(&args.include);
+
+    let key = cache_key(base_path, args);
+    if args.cache && !args.refresh {
+        if let Some(paths) = cache::lookup(&key) {
+            return paths
+                .into_iter()
+                .filter_map(|path| {
+                    let repo = open_git_repo(&path)?;
+                    let relative_path = path.strip_prefix(base_path).unwrap_or(&path).to_path_buf();
+                    Some((path, relative_path, repo))
+                })
+                .collect();
+        }
+    }
+
+    let mut walker = WalkDir::new(base_path).follow_links(args.follow_links);
+    if let Some(max_depth) = args.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    let mut entries = walker.into_iter().filter_entry(|entry| {
+        let skipped = entry.file_type().is_dir()
+            && entry.file_name().to_str().is_some_and(|name| args.skip_dirs.iter().any(|dir| dir == name));
+        entry.depth() == 0 || !skipped
+    });
+
+    let mut repos = Vec::new();
+    let mut searched_dirs = Vec::new();
+    let mut seen = HashSet::new();
+    while let Some(entry) = entries.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // WalkDir reports a link back to one of its own ancestors instead of descending into it
+                if let (Some(path), Some(ancestor)) = (e.path(), e.loop_ancestor()) {
+                    info!(args, "Skipping symlink loop at {:?}, which points back to {:?}", path, ancestor);
+                }
+                continue;
+            }
+        };
+        let path = entry.path().to_owned();
+        let relative_path = path.strip_prefix(base_path).unwrap_or(&path).to_path_buf();
+        let repo = open_git_repo(&path);
+        if args.cache && repo.is_none() && entry.file_type().is_dir() {
+            searched_dirs.extend(cache::mtime(&path).map(|modified| (path.clone(), modified)));
+        }
+        // Everything below a repo root belongs to that repo unless nested repos were asked for,
+        // whether or not the filters below keep the repo itself
+        if repo.is_some() && !args.nested && entry.file_type().is_dir() {
+            entries.skip_current_dir();
+        }
+        if excludes.is_match(&relative_path) {
+            // Nothing below an excluded directory is wanted either, so don't walk it
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+            continue;
+        }
+        if !includes.is_empty() && !includes.is_match(&relative_path) {
+            continue;
+        }
+
+        if let Some(repo) = repo {
+            // The same repo can be reached through more than one link
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if args.follow_links && !seen.insert(canonical) {
+                if entry.file_type().is_dir() {
+                    entries.skip_current_dir();
+                }
+                continue;
+            }
+            repos.push((path, relative_path, repo));
+        }
+    }
+    repos.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+
+    if args.cache {
+        let paths = repos.iter().map(|(path, _, _)| path.clone()).collect();
+        if let Err(e) = cache::store(&key, paths, searched_dirs) {
+            error!("Failed to write the repo cache: {}", e);
+        }
+    }
+    repos
+}
+
+/// Reads the exclude globs from the base path's `.mprignore`, skipping blank lines, `#` comments and invalid patterns
+fn read_ignore_file(base_path: &Path) -> Vec<Glob> {
+    let path = base_path.join(IGNORE_FILE);
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // A trailing slash marks a directory in .gitignore, and every repo is one
+        .map(|line| line.strip_suffix('/').unwrap_or(line))
+        .filter_map(|pattern| match Glob::new(pattern) {
+            Ok(glob) => Some(glob),
+            Err(e) => {
+                error!("Ignoring invalid pattern {:?} in {:?}: {}", pattern, path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Whether the repo has `remote` and its URL matches `pattern`
+fn remote_matches(repo: &Repository, remote: &str, pattern: &Regex) -> bool {
+    repo.find_remote(remote).is_ok_and(|remote| remote.url().is_some_and(|url| pattern.is_match(url)))
+}
+
+/// Identifies a search in the repo cache by its base path and every option that changes what it finds
+fn cache_key(base_path: &Path, args: &Args) -> String {
+    let base_path = base_path.canonicalize().unwrap_or_else(|_| base_path.to_path_buf());
+    let globs = |globs: &[Glob]| globs.iter().map(Glob::glob).collect::<Vec<_>>().join(",");
+    format!(
+        "{}|depth={:?}|skip={}|nested={}|links={}|exclude={}|include={}",
+        base_path.display(),
+        args.max_depth,
+        args.skip_dirs.join(","),
+        args.nested,
+        args.follow_links,
+        globs(&args.exclude),
+        globs(&args.include),
+    )
+}
+
+/// Reads the repos named in `list`, one path per line with relative ones under `base_path`, reporting and skipping any that aren't git repos
+fn read_repository_list(list: &Path, base_path: &Path) -> Vec<(PathBuf, PathBuf, Repository)> {
+    let contents = match std::fs::read_to_string(list) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Failed to read repo list {:?}: {}", list, e);
+            return Vec::new();
+        }
+    };
+
+    let mut repos = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Relative entries are relative to the base path, like the repos it finds, not to the current directory
+        let path = base_path.join(line);
+        if !path.exists() {
+            error!("Listed repo {:?} does not exist", path);
+        } else if let Some(repo) = open_git_repo(&path) {
+            let relative_path = path.strip_prefix(base_path).unwrap_or(&path).to_path_buf();
+            repos.push((path, relative_path, repo));
+        } else {
+            error!("Listed path {:?} is not a git repo", path);
+        }
+    }
+    repos
+}
+
+/// Prints the totals for the run followed by the paths of any failed repos
+fn print_summary(results: &[RepoResult], args: &Args) {
+    let count = |outcome: Outcome| results.iter().filter(|result| result.outcome == outcome).count();
+
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    let _ = writeln!(stdout);
+    let _ = writeln!(stdout, "Summary");
+    let _ = writeln!(stdout, "  Total:     {}", results.len());
+    let _ = writeln!(stdout, "  Succeeded: {}", count(Outcome::Succeeded));
+    let _ = writeln!(stdout, "  Failed:    {}", count(Outcome::Failed));
+    let _ = writeln!(stdout, "  Skipped:   {}", count(Outcome::Skipped));
+    let _ = writeln!(stdout, "  Timed out: {}", count(Outcome::TimedOut));
+    let _ = writeln!(stdout, "  Diverged:  {}", count(Outcome::Diverged));
+    let _ = writeln!(stdout, "  Conflicted: {}", count(Outcome::Conflicted));
+
+    let mut retried: Vec<&RepoResult> = results.iter().filter(|result| result.attempts > 1).collect();
+    if !retried.is_empty() {
+        retried.sort_by(|a, b| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "Retried repos:");
+        for result in retried {
+            let _ = writeln!(stdout, "  {} ({} attempts)", result.path.display(), result.attempts);
+        }
+    }
+
+    if let Some(limit) = args.slowest.filter(|&limit| limit > 0) {
+        let mut slowest: Vec<&RepoResult> = results.iter().collect();
+        slowest.sort_by(|a, b| b.duration_secs.total_cmp(&a.duration_secs));
+        let _ = writeln!(stdout, "Slowest repos:");
+        for result in slowest.into_iter().take(limit) {
+            let _ = writeln!(stdout, "  {:>8.1}s  {}", result.duration_secs, result.path.display());
+        }
+    }
+
+    let mut diverged: Vec<&RepoResult> = results.iter().filter(|result| result.outcome == Outcome::Diverged).collect();
+    if !diverged.is_empty() {
+        diverged.sort_by(|a, b| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "Diverged repos:");
+        let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)));
+        for result in diverged {
+            let _ = writeln!(stdout, "  {}", result.path.display());
+        }
+        let _ = stdout.reset();
+    }
+
+    let mut pruned: Vec<&RepoResult> = results.iter().filter(|result| result.pruned > 0).collect();
+    if !pruned.is_empty() {
+        pruned.sort_by(|a, b| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "Pruned stale refs:");
+        for result in pruned {
+            let _ = writeln!(stdout, "  {} ({})", result.path.display(), result.pruned);
+        }
+    }
+
+    let mut lfs_pulls: Vec<(&RepoResult, LfsPull)> =
+        results.iter().filter_map(|result| Some((result, result.lfs?))).collect();
+    if !lfs_pulls.is_empty() {
+        lfs_pulls.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "LFS pulls:");
+        for (result, lfs) in lfs_pulls {
+            let status = if lfs.outcome == Outcome::Succeeded { "" } else { ", failed" };
+            let megabytes = lfs.bytes as f64 / 1_000_000.0;
+            let _ = writeln!(
+                stdout,
+                "  {} ({:.1} MB in {:.1}s{})",
+                result.path.display(),
+                megabytes,
+                lfs.duration_secs,
+                status
+            );
+        }
+    }
+
+    let mut submodule_failures: Vec<&RepoResult> =
+        results.iter().filter(|result| result.submodules == Some(Outcome::Failed)).collect();
+    if !submodule_failures.is_empty() {
+        submodule_failures.sort_by(|a, b| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "Submodule update failed in:");
+        for result in submodule_failures {
+            let _ = writeln!(stdout, "  {}", result.path.display());
+        }
+    }
+
+    let mut conflicted: Vec<&RepoResult> =
+        results.iter().filter(|result| result.outcome == Outcome::Conflicted).collect();
+    if !conflicted.is_empty() {
+        conflicted.sort_by(|a, b| a.path.cmp(&b.path));
+        let _ = writeln!(stdout, "Conflicted repos:");
+        for result in conflicted {
+            let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+            let _ = writeln!(stdout, "  {} CONFLICTED", result.path.display());
+            let _ = stdout.reset();
+            for conflict in &result.conflicts {
+                let _ = writeln!(stdout, "    {}", conflict);
+            }
+        }
+    }
+
+    let mut failed: Vec<&RepoResult> = results
+        .iter()
+        .filter(|result| result.outcome.is_failure() && !matches!(result.outcome, Outcome::Diverged | Outcome::Conflicted))
+        .collect();
+    if failed.is_empty() {
+        return;
+    }
+    failed.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let _ = writeln!(stdout, "Failed repos:");
+    let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+    let mut causes: BTreeMap<RepoError, usize> = BTreeMap::new();
+    for result in failed {
+        let cause = result.error.or((result.outcome == Outcome::TimedOut).then_some(RepoError::TimedOut));
+        match cause {
+            Some(cause) => {
+                *causes.entry(cause).or_default() += 1;
+                let _ = writeln!(stdout, "  {} ({})", result.path.display(), cause.label());
+            }
+            None => {
+                let _ = writeln!(stdout, "  {}", result.path.display());
+            }
+        }
+    }
+    let _ = stdout.reset();
+
+    if causes.len() > 1 {
+        let counts: Vec<String> = causes.iter().map(|(cause, count)| format!("{} {}", count, cause.label())).collect();
+        let _ = writeln!(stdout, "Failures by cause: {}", counts.join(", "));
+    }
+}
+
+
+/// Runs the selected action in one repo, reusing the handle opened during discovery for git queries
+async fn process_repository(full_path: &Path, repo: Repository, args: &Args, relative_path: &Path) -> RepoResult {
+    let started = Instant::now();
+
+    let worktree_of = main_worktree(&repo);
+    // Clean repos are meant to stay silent when listing dirty ones
+    if !matches!(args.action, Some(Action::Dirty)) {
+        match &worktree_of {
+            Some(main) => info!(args, "Found repository: {:?} (worktree of {:?})", relative_path, main),
+            None => info!(args, "Found repository: {:?}", relative_path),
+        }
+    }
+    let _ = REPO_STATE.try_with(|state| state.branch.replace(Some(current_branch(&repo))));
+
+    let mut submodules = None;
+    let mut lfs = None;
+    let bare = repo.is_bare();
+    let outcome = match &args.action {
+        // A bare mirror has no working tree to pull into or install in, so fetching is all that applies
+        Some(Action::Update) | None if bare && args.only_deps => {
+            info!(args, "{:?} is a bare mirror with no dependencies to install, skipping", relative_path);
+            Outcome::Skipped
+        }
+        Some(Action::Pull | Action::Update) | None if bare => {
+            info!(args, "{:?} is a bare mirror, fetching instead of pulling", relative_path);
+            fetch_repo(full_path, relative_path, args).await
+        }
+        Some(
+            Action::Status
+            | Action::Dirty
+            | Action::Audit
+            | Action::Outdated
+            | Action::Commit { .. }
+            | Action::Checkout { .. }
+            | Action::Branch { .. }
+            | Action::Reset { .. },
+        ) if bare => {
+            info!(args, "{:?} is a bare repo without a working tree, skipping", relative_path);
+            Outcome::Skipped
+        }
+        Some(Action::Pull) => {
+            let worktree_lock = worktree_lock(&repo);
+            let _shared_refs = worktree_lock.lock().await;
+            let pulled = pull_repo(full_path, relative_path, args).await;
+            submodules = update_submodules(full_path, relative_path, args, pulled).await;
+            lfs = pull_lfs(full_path, &common_dir(&repo), relative_path, args, pulled).await;
+            pulled.and(submodules.unwrap_or(Outcome::Skipped)).and(lfs.map_or(Outcome::Skipped, |lfs| lfs.outcome))
+        }
+        Some(Action::Status) => status_repo(&repo, relative_path, args),
+        Some(Action::Dirty) => dirty_repo(&repo, relative_path, args),
+        Some(Action::Tags { count }) => tags_repo(&repo, *count, relative_path, args),
+        Some(Action::Push) => push_repo(full_path, relative_path, args).await,
+        Some(Action::Fetch) => fetch_repo(full_path, relative_path, args).await,
+        Some(Action::Audit) => audit_repo(full_path, relative_path, args).await,
+        Some(Action::Outdated) => outdated_repo(full_path, relative_path, args).await,
+        Some(Action::Commit { message, push }) => commit_repo(full_path, message, *push, relative_path, args).await,
+        Some(Action::Exec { command }) => exec_repo(full_path, command, relative_path, args).await.into(),
+        Some(Action::Checkout { branch }) => checkout_repo(full_path, branch, relative_path, args).await,
+        Some(Action::Branch { name, base, push_new }) => {
+            create_branch(full_path, name, base.as_deref(), *push_new, relative_path, args).await
+        }
+        Some(Action::Gc { aggressive }) => gc_repo(full_path, &common_dir(&repo), *aggressive, relative_path, args).await,
+        Some(Action::Reset { clean, .. }) => {
+            let discarded = discarded_by_reset(&repo, *clean);
+            reset_repo(full_path, discarded, *clean, relative_path, args).await
+        }
+        Some(Action::Clone { .. }) => unreachable!("clone is handled before discovery"),
+        Some(Action::Completions { .. }) => unreachable!("completions are printed before anything else runs"),
+        Some(Action::Update) | None if args.only_deps => update_dependencies(full_path, relative_path, args).await,
+        Some(Action::Update) | None => 'steps: {
+            let worktree_lock = worktree_lock(&repo);
+            let shared_refs = worktree_lock.lock().await;
+            let pulled = pull_repo(full_path, relative_path, args).await;
+            if fail_fast(pulled, relative_path, args) {
+                break 'steps pulled;
+            }
+            submodules = update_submodules(full_path, relative_path, args, pulled).await;
+            let so_far = pulled.and(submodules.unwrap_or(Outcome::Skipped));
+            if fail_fast(so_far, relative_path, args) {
+                break 'steps so_far;
+            }
+            lfs = pull_lfs(full_path, &common_dir(&repo), relative_path, args, pulled).await;
+            let so_far = so_far.and(lfs.as_ref().map_or(Outcome::Skipped, |lfs| lfs.outcome));
+            if fail_fast(so_far, relative_path, args) {
+                break 'steps so_far;
+            }
+            // Installs only touch this worktree, so the other worktrees can pull meanwhile
+            drop(shared_refs);
+            let updated = if args.no_deps {
+                Outcome::Skipped
+            } else {
+                update_dependencies(full_path, relative_path, args).await
+            };
+            so_far.and(updated)
+        }
+    };
+
+    let tracking = ahead_behind(&repo);
+    tracing::info!(
+        repo = %relative_path.display(),
+        action = args.action.as_ref().map_or("update", Action::name),
+        outcome = ?outcome,
+        duration_secs = started.elapsed().as_secs_f64(),
+        "repo finished"
+    );
+
+    RepoResult {
+        path: relative_path.to_path_buf(),
+        action: args.action.as_ref().map_or("update", Action::name),
+        outcome,
+        exit_code: if outcome.is_failure() { 1 } else { 0 },
+        duration_secs: started.elapsed().as_secs_f64(),
+        ahead: tracking.map(|(ahead, _)| ahead),
+        behind: tracking.map(|(_, behind)| behind),
+        attempts: 0,
+        pruned: 0,
+        submodules,
+        conflicts: Vec::new(),
+        worktree_of,
+        lfs,
+        error: None,
+    }
+}
+
+/// Under `--fail-fast`, cancels the run as soon as one of a repo's steps fails, which stops the other repos
+/// and refuses this repo's remaining commands; returns whether the run has been cancelled
+fn fail_fast(step: Outcome, relative_path: &Path, args: &Args) -> bool {
+    let Some(cancel) = CANCEL.get() else {
+        return false;
+    };
+    if args.fail_fast && step.is_failure() && !cancel.is_cancelled() {
+        cancel.cancel();
+        let _ = REPO_STATE.try_with(|state| state.failed_fast.set(true));
+        error!("{:?} failed, cancelling the remaining repos (--fail-fast)", relative_path);
+    }
+    if !cancel.is_cancelled() {
+        return false;
+    }
+    // Stopping because another repo failed leaves this one cut short, as a refused command would
+    let _ = REPO_STATE.try_with(|state| {
+        if !state.failed_fast.get() {
+            state.cancelled.set(true);
+        }
+    });
+    true
+}
+
+/// Compiles glob patterns into a single matcher
+fn build_globset(patterns: &[Glob]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(pattern.clone());
+    }
+    builder.build().expect("Globs were validated while parsing arguments")
+}
+
+/// The lock serializing git steps across every worktree that shares this repo's common dir
+fn worktree_lock(repo: &Repository) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = WORKTREE_LOCKS.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+    Arc::clone(locks.entry(common_dir(repo)).or_default())
+}
+
+/// Working directory of the repo a linked worktree was added from, or `None` for anything but a linked worktree
+fn main_worktree(repo: &Repository) -> Option<PathBuf> {
+    if !repo.is_worktree() {
+        return None;
+    }
+    // The common dir is the main repo's `.git`, or the repo itself when it is bare
+    let common_dir = common_dir(repo);
+    match common_dir.file_name() {
+        Some(name) if name == ".git" => common_dir.parent().map(Path::to_path_buf),
+        _ => Some(common_dir),
+    }
+}
+
+/// Git directory shared by a repo and all of its worktrees, which a linked worktree's
+/// own git directory names in its `commondir` file
+fn common_dir(repo: &Repository) -> PathBuf {
+    let git_dir = repo.path();
+    let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(contents) => git_dir.join(contents.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    };
+    common_dir.canonicalize().unwrap_or(common_dir)
+}
+
+/// Opens the directory as a Git repository, or returns `None` if it isn't one
+fn open_git_repo(path: &Path) -> Option<Repository> {
+    Repository::open(path).ok()
+}
+
+/// Reports the branch, working tree state and upstream divergence of the repository
+fn status_repo(repo: &Repository, relative_path: &Path, args: &Args) -> Outcome {
+    let branch = current_branch(repo);
+    let dirty = is_dirty(repo, true);
+    let tracking = tracking_label(repo);
+
+    let (state, color) = if dirty { ("dirty", Color::Yellow) } else { ("clean", Color::Green) };
+    let message = format!("{} {} ({})\n", branch, state, tracking);
+
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, "Status", &message, color, relative_path, args.is_json()).unwrap();
+
+    Outcome::Succeeded
+}
+
+/// Prints the number of staged, modified and untracked files if the working tree has any changes
+fn dirty_repo(repo: &Repository, relative_path: &Path, args: &Args) -> Outcome {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let statuses = match repo.statuses(Some(&mut options)) {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            error!("Failed to read the status of {:?}: {}", relative_path, e);
+            return Outcome::Failed;
+        }
+    };
+
+    let staged_flags = Status::INDEX_NEW
+        | Status::INDEX_MODIFIED
+        | Status::INDEX_DELETED
+        | Status::INDEX_RENAMED
+        | Status::INDEX_TYPECHANGE;
+    let modified_flags =
+        Status::WT_MODIFIED | Status::WT_DELETED | Status::WT_RENAMED | Status::WT_TYPECHANGE | Status::CONFLICTED;
+    let count = |flags: Status| statuses.iter().filter(|entry| entry.status().intersects(flags)).count();
+    let (staged, modified, untracked) = (count(staged_flags), count(modified_flags), count(Status::WT_NEW));
+    if staged + modified + untracked == 0 {
+        return Outcome::Succeeded;
+    }
+
+    let message = format!("{} staged, {} modified, {} untracked\n", staged, modified, untracked);
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, "Dirty", &message, Color::Yellow, relative_path, args.is_json()).unwrap();
+    Outcome::Succeeded
+}
+
+/// Prints the `count` tags whose commits are newest
+fn tags_repo(repo: &Repository, count: usize, relative_path: &Path, args: &Args) -> Outcome {
+    let names = match repo.tag_names(None) {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Failed to list the tags of {:?}: {}", relative_path, e);
+            return Outcome::Failed;
+        }
+    };
+
+    let mut tags: Vec<(i64, &str)> = names
+        .iter()
+        .flatten()
+        .filter_map(|name| {
+            let commit = repo.revparse_single(&format!("refs/tags/{}", name)).ok()?.peel_to_commit().ok()?;
+            Some((commit.time().seconds(), name))
+        })
+        .collect();
+    tags.sort_by(|a, b| b.cmp(a));
+
+    let message = if tags.is_empty() {
+        "no tags\n".to_string()
+    } else {
+        let latest: Vec<&str> = tags.iter().take(count).map(|(_, name)| *name).collect();
+        format!("{}\n", latest.join(", "))
+    };
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, "Tags", &message, Color::Green, relative_path, args.is_json()).unwrap();
+    Outcome::Succeeded
+}
+
+/// Returns the name of the checked out branch, or the short commit hash when detached
+fn current_branch(repo: &Repository) -> String {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(_) => return "(no commits)".to_string(),
+    };
+
+    if repo.head_detached().unwrap_or(false) {
+        return head
+            .target()
+            .map(|oid| oid.to_string()[..7].to_string())
+            .unwrap_or_else(|| "(detached)".to_string());
+    }
+
+    head.shorthand().unwrap_or("(unknown)").to_string()
+}
+
+/// Checks whether the working tree or index has changes
+fn is_dirty(repo: &Repository, include_untracked: bool) -> bool {
+    let mut options = StatusOptions::new();
+    options.include_untracked(include_untracked).include_ignored(false);
+
+    repo.statuses(Some(&mut options))
+        .map(|statuses| !statuses.is_empty())
+        .unwrap_or(false)
+}
+
+/// Counts commits ahead of and behind the upstream of the checked out branch
+fn ahead_behind(repo: &Repository) -> Option<(usize, usize)> {
+    let local_oid = repo.head().ok()?.target()?;
+    let upstream_oid = upstream_oid(repo)?;
+
+    repo.graph_ahead_behind(local_oid, upstream_oid).ok()
+}
+
+/// Formats the ahead/behind counts as `+N/-M`, or "no upstream" when there is none
+fn tracking_label(repo: &Repository) -> String {
+    match ahead_behind(repo) {
+        Some((ahead, behind)) => format!("+{}/-{}", ahead, behind),
+        None => "no upstream".to_string(),
+    }
+}
+
+/// Resolves the commit the upstream of the checked out branch points at
+fn upstream_oid(repo: &Repository) -> Option<Oid> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+
+    let branch = repo.find_branch(head.shorthand()?, BranchType::Local).ok()?;
+    let upstream = branch.upstream().ok()?;
+
+    upstream.get().target()
+}
+
+/// Pulls the latest changes in the repository
+async fn pull_repo(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    match &args.pull_branch {
+        Some(branch) => pull_other_branch(path, branch, relative_path, args).await,
+        None => pull_checked_out(path, relative_path, args).await,
+    }
+}
+
+/// Switches to `branch` to pull it, then back to the previously checked out branch unless `--stay` was given
+async fn pull_other_branch(path: &Path, branch: &str, relative_path: &Path, args: &Args) -> Outcome {
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    let (previous, dirty) = match Repository::open(path) {
+        Ok(repo) if repo.find_branch(branch, BranchType::Local).is_err() => {
+            let message = format!("No local branch '{}', skipping pull\n", branch);
+            print_with_prefix(&mut stdout, "Git", &message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+            return Outcome::Skipped;
+        }
+        Ok(repo) => (current_branch(&repo), is_dirty(&repo, false)),
+        Err(e) => {
+            error!("Failed to open repository {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    };
+    if previous == branch {
+        return pull_checked_out(path, relative_path, args).await;
+    }
+    if dirty {
+        let message = format!("Working tree has uncommitted changes, not switching to '{}' to pull it\n", branch);
+        print_with_prefix(&mut stdout, "Git", &message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    }
+
+    if run_command(path, "git", &["checkout", branch], "Git", relative_path, args).await.is_err() {
+        error!("Failed to switch {:?} to {}, skipping pull", relative_path, branch);
+        return Outcome::Failed;
+    }
+    let _ = REPO_STATE.try_with(|state| state.branch.replace(Some(branch.to_string())));
+
+    let outcome = pull_checked_out(path, relative_path, args).await;
+    // A conflicted merge has to be resolved on the pulled branch, so leave it checked out
+    if args.stay || (outcome == Outcome::Conflicted && !args.abort_on_conflict) {
+        return outcome;
+    }
+
+    if run_command(path, "git", &["checkout", &previous], "Git", relative_path, args).await.is_err() {
+        error!("Pulled {} in {:?} but failed to switch back to {}", branch, relative_path, previous);
+        return Outcome::Failed;
+    }
+    let _ = REPO_STATE.try_with(|state| state.branch.replace(Some(previous)));
+    outcome
+}
+
+/// Pulls the checked out branch, guarding against detached heads and uncommitted changes
+async fn pull_checked_out(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    info!(args, "Pulling repository at {:?}", relative_path);
+    if is_missing_remote(path, relative_path, args) {
+        return Outcome::Skipped;
+    }
+
+    let prefix = if args.rebase { "Git rebase" } else { "Git merge" };
+
+    let detached = Repository::open(path)
+        .and_then(|repo| repo.head_detached())
+        .unwrap_or(false);
+    if detached && !args.force {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = "HEAD is detached (mid-bisect or checked out commit?), skipping pull (use --force to override)\n";
+        print_with_prefix(&mut stdout, prefix, message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    }
+    // `--force` can't help here: `--remote` pulls the same-named branch, and a detached HEAD has no name
+    if detached && args.remote.is_some() {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = "HEAD is detached, so there is no branch to pull from --remote, skipping pull\n";
+        print_with_prefix(&mut stdout, prefix, message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    }
+
+    let dirty = Repository::open(path)
+        .map(|repo| is_dirty(&repo, args.untracked_dirty))
+        .unwrap_or(false);
+
+    if dirty && !args.force && !args.autostash {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = "Working tree has uncommitted changes, skipping pull (use --force or --autostash to override)\n";
+        print_with_prefix(&mut stdout, prefix, message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    }
+
+    let stashed = dirty && args.autostash;
+    if stashed {
+        let mut stash_args = vec!["stash", "push", "-m", AUTOSTASH_MESSAGE];
+        if args.untracked_dirty {
+            stash_args.push("--include-untracked");
+        }
+        if run_command(path, "git", &stash_args, "Git stash", relative_path, args).await.is_err() {
+            error!("Failed to stash local changes in {:?}, skipping pull", relative_path);
+            return Outcome::Failed;
+        }
+    }
+
+    let refs_before = args.prune.then(|| remote_refs(path));
+    let mut outcome = pull_with_strategy(path, relative_path, args, prefix).await;
+    if let Some(refs_before) = refs_before {
+        record_pruned(path, &refs_before);
+    }
+
+    let unresolved = outcome == Outcome::Conflicted && !args.abort_on_conflict;
+    if stashed && unresolved {
+        error!(
+            "Local changes in {:?} were left in the stash '{}' until the merge conflicts are resolved",
+            relative_path, AUTOSTASH_MESSAGE
+        );
+    } else if stashed && run_command(path, "git", &["stash", "pop"], "Git stash", relative_path, args).await.is_err() {
+        error!(
+            "Restoring stashed changes in {:?} conflicted; the stash '{}' was kept and needs manual attention",
+            relative_path, AUTOSTASH_MESSAGE
+        );
+        outcome = Outcome::Failed;
+    }
+
+    if let Ok(repo) = Repository::open(path) {
+        let message = format!("{} ({})\n", current_branch(&repo), tracking_label(&repo));
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        print_with_prefix(&mut stdout, prefix, &message, Color::Green, relative_path, args.is_quiet()).unwrap();
+    }
+
+    outcome
+}
+
+/// Pulls using the rebase, git CLI or native strategy selected on the command line
+async fn pull_with_strategy(path: &Path, relative_path: &Path, args: &Args, prefix: &str) -> Outcome {
+    if args.rebase {
+        let mut git_args = vec!["pull", "--rebase"];
+        if args.prune {
+            git_args.push("--prune");
+        }
+        if args.tags {
+            git_args.push("--tags");
+        }
+        let remote_args = remote_branch_args(path, args);
+        git_args.extend(remote_args.iter().map(String::as_str));
+        if run_git_with_retries(path, &git_args, prefix, relative_path, args).await.is_ok() {
+            return Outcome::Succeeded;
+        }
+        if is_rebasing(path) {
+            let _ = run_command(path, "git", &["rebase", "--abort"], prefix, relative_path, args).await;
+            error!("Rebase of {:?} hit conflicts and was aborted", relative_path);
+        }
+        return Outcome::Failed;
+    }
+
+    if args.use_git_cli {
+        let mut git_args = vec!["pull"];
+        if args.ff_only {
+            git_args.push("--ff-only");
+        }
+        if args.prune {
+            git_args.push("--prune");
+        }
+        if args.tags {
+            git_args.push("--tags");
+        }
+        let remote_args = remote_branch_args(path, args);
+        git_args.extend(remote_args.iter().map(String::as_str));
+        return match run_git_with_retries(path, &git_args, prefix, relative_path, args).await {
+            Ok(()) => Outcome::Succeeded,
+            Err(CommandError::Failed(stderr)) if args.ff_only && stderr.contains("Not possible to fast-forward") => {
+                Outcome::Diverged
+            }
+            Err(CommandError::Failed(_)) if is_merging(path) => report_conflicts(path, prefix, relative_path, args).await,
+            Err(CommandError::TimedOut) => Outcome::TimedOut,
+            Err(_) => Outcome::Failed,
+        };
+    }
+
+    if args.dry_run {
+        let mut git_args = vec!["pull", "--ff-only"];
+        if args.prune {
+            git_args.push("--prune");
+        }
+        if args.tags {
+            git_args.push("--tags");
+        }
+        let remote_args = remote_branch_args(path, args);
+        git_args.extend(remote_args.iter().map(String::as_str));
+
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("Would run the native equivalent of `git {}` in {:?}\n", git_args.join(" "), path);
+        print_with_prefix(&mut stdout, prefix, &message, Color::Cyan, relative_path, args.is_json()).unwrap();
+        return Outcome::Succeeded;
+    }
+
+    let mut attempt = 1;
+    let outcome = loop {
+        let repo_path = path.to_path_buf();
+        let deadline = args.timeout.map(|secs| Instant::now() + Duration::from_secs(secs));
+        let cancel = CANCEL.get().cloned();
+        let options = PullOptions {
+            ssh_key: args.ssh_key.clone(),
+            prune: args.prune,
+            remote: args.remote.clone(),
+            tags: args.tags,
+            deadline,
+            cancel: cancel.clone(),
+        };
+        let pull = tokio::task::spawn_blocking(move || native::pull(&repo_path, &options));
+        // The callbacks abort the fetch once the deadline passes or the run is cancelled, but a stalled
+        // connection never calls them, so the wait is bounded as well and the blocking thread is left
+        // to give up on its own
+        let bounded = async {
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), pull).await.ok(),
+                None => Some(pull.await),
+            }
+        };
+        let joined = match &cancel {
+            Some(cancel) => tokio::select! {
+                joined = bounded => joined,
+                () = cancel.cancelled() => None,
+            },
+            None => bounded.await,
+        };
+        let outcome = match joined {
+            Some(Ok(outcome)) => outcome,
+            Some(Err(e)) => {
+                error!("Native pull of {:?} panicked: {}", relative_path, e);
+                record_error(RepoError::Panicked);
+                return Outcome::Failed;
+            }
+            None => Err(git2::Error::from_str("stopped")),
+        };
+        if outcome.is_err() && cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            record_attempts(attempt);
+            error!("Cancelled pulling {:?} because another repo failed", relative_path);
+            let _ = REPO_STATE.try_with(|state| state.cancelled.set(true));
+            return Outcome::Skipped;
+        }
+        if outcome.is_err() && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            record_attempts(attempt);
+            error!("Timed out after {}s pulling {:?}, abandoned the pull", args.timeout.unwrap_or_default(), relative_path);
+            let _ = REPO_STATE.try_with(|state| state.timed_out.set(true));
+            record_error(RepoError::TimedOut);
+            return Outcome::TimedOut;
+        }
+        match outcome {
+            Err(e) if attempt <= args.retries && native::is_network_error(&e) => {
+                wait_before_retry(attempt, e.message(), relative_path, args).await;
+                attempt += 1;
+            }
+            outcome => break outcome,
+        }
+    };
+    record_attempts(attempt);
+
+    // The native path never merges, so a diverged branch is left untouched and reported as such
+    // rather than as a successful pull
+    let (message, color, pulled) = match outcome {
+        Ok(PullOutcome::UpToDate) => ("Already up to date".to_string(), Color::Green, Outcome::Succeeded),
+        Ok(PullOutcome::FastForwarded(commits)) => {
+            (format!("Fast-forwarded {} commit(s)", commits), Color::Green, Outcome::Succeeded)
+        }
+        Ok(PullOutcome::MergeNeeded) if args.ff_only => {
+            ("Branch has diverged from upstream, not fast-forwarding".to_string(), Color::Yellow, Outcome::Diverged)
+        }
+        Ok(PullOutcome::MergeNeeded) => (
+            "Branch has diverged from upstream, merge needed (rerun with --use-git-cli to merge)".to_string(),
+            Color::Yellow,
+            Outcome::Diverged,
+        ),
+        Err(e) => {
+            record_error(RepoError::Git);
+            (format!("Pull failed: {}", e.message()), Color::Red, Outcome::Failed)
+        }
+    };
+
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    let quiet = args.is_quiet() && pulled != Outcome::Failed;
+    print_with_prefix(&mut stdout, prefix, &format!("{}\n", message), color, relative_path, quiet).unwrap();
+
+    pulled
+}
+
+/// Updates submodules after a successful pull when `--submodules` is given and the repo declares any
+async fn update_submodules(path: &Path, relative_path: &Path, args: &Args, pulled: Outcome) -> Option<Outcome> {
+    if !args.submodules || pulled != Outcome::Succeeded || !path.join(".gitmodules").is_file() {
+        return None;
+    }
+
+    let git_args = ["submodule", "update", "--init", "--recursive"];
+    let updated = run_command(path, "git", &git_args, "Git submodule", relative_path, args).await.is_ok();
+    if !updated {
+        error!("Pulled {:?}, but updating its submodules failed", relative_path);
+    }
+    Some(updated.into())
+}
+
+/// Runs `git lfs pull` after a successful pull when `--lfs` is given and the repo tracks files with LFS
+async fn pull_lfs(path: &Path, git_dir: &Path, relative_path: &Path, args: &Args, pulled: Outcome) -> Option<LfsPull> {
+    if !args.lfs || pulled != Outcome::Succeeded || !uses_lfs(path) {
+        return None;
+    }
+
+    if args.dry_run {
+        let _ = run_command(path, "git", &["lfs", "pull"], "Git LFS", relative_path, args).await;
+        return None;
+    }
+
+    let objects = git_dir.join("lfs").join("objects");
+    let size_before = directory_size(&objects);
+    let started = Instant::now();
+    let pulled = run_command(path, "git", &["lfs", "pull"], "Git LFS", relative_path, args).await.is_ok();
+    if !pulled {
+        error!("Pulled {:?}, but fetching its LFS objects failed", relative_path);
+    }
+    Some(LfsPull {
+        outcome: pulled.into(),
+        bytes: directory_size(&objects).saturating_sub(size_before),
+        duration_secs: started.elapsed().as_secs_f64(),
+    })
+}
+
+/// Checks whether `.gitattributes` routes any files through the LFS filter
+fn uses_lfs(path: &Path) -> bool {
+    std::fs::read_to_string(path.join(".gitattributes"))
+        .is_ok_and(|attributes| attributes.lines().any(|line| line.contains("filter=lfs")))
+}
+
+/// Total size of the files below `dir`, 0 if it doesn't exist
+fn directory_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Records the paths a pull left conflicted, aborting the merge under `--abort-on-conflict`
+async fn report_conflicts(path: &Path, prefix: &str, relative_path: &Path, args: &Args) -> Outcome {
+    let conflicts = conflicted_paths(path);
+    error!("Pull of {:?} stopped with merge conflicts in: {}", relative_path, conflicts.join(", "));
+
+    if args.abort_on_conflict {
+        if run_command(path, "git", &["merge", "--abort"], prefix, relative_path, args).await.is_ok() {
+            info!(args, "Aborted the conflicted merge in {:?}", relative_path);
+        } else {
+            error!("Failed to abort the conflicted merge in {:?}", relative_path);
+        }
+    }
+
+    let _ = REPO_STATE.try_with(|state| state.conflicts.replace(conflicts));
+    Outcome::Conflicted
+}
+
+/// Lists the paths with conflicts in the index
+fn conflicted_paths(path: &Path) -> Vec<String> {
+    let Ok(index) = Repository::open(path).and_then(|repo| repo.index()) else {
+        return Vec::new();
+    };
+    let Ok(conflicts) = index.conflicts() else {
+        return Vec::new();
+    };
+    conflicts
+        .filter_map(Result::ok)
+        .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        .collect()
+}
+
+/// Checks whether the repository is stuck in the middle of a merge
+fn is_merging(path: &Path) -> bool {
+    Repository::open(path).is_ok_and(|repo| repo.state() == RepositoryState::Merge)
+}
+
+/// Checks whether the repository is stuck in the middle of a rebase
+fn is_rebasing(path: &Path) -> bool {
+    Repository::open(path)
+        .map(|repo| {
+            matches!(
+                repo.state(),
+                RepositoryState::Rebase | RepositoryState::RebaseInteractive | RepositoryState::RebaseMerge
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// Runs a git network command, retrying up to `--retries` times when it fails with a network error
+async fn run_git_with_retries(
+    path: &Path,
+    git_args: &[&str],
+    prefix: &str,
+    relative_path: &Path,
+    args: &Args,
+) -> Result<(), CommandError> {
+    let mut attempt = 1;
+    loop {
+        match run_command(path, "git", git_args, prefix, relative_path, args).await {
+            Err(CommandError::Failed(stderr)) if attempt <= args.retries && is_network_error(&stderr) => {
+                wait_before_retry(attempt, stderr.lines().last().unwrap_or_default(), relative_path, args).await;
+                attempt += 1;
+            }
+            result => {
+                record_attempts(attempt);
+                return result;
+            }
+        }
+    }
+}
+
+/// Checks git's stderr for failures worth retrying; auth and merge failures are never retried
+fn is_network_error(stderr: &str) -> bool {
+    const AUTH: &[&str] = &["Permission denied", "Authentication failed", "could not read Username", "returned error: 403"];
+    const NETWORK: &[&str] = &[
+        "Could not resolve host",
+        "Connection timed out",
+        "Connection refused",
+        "Connection reset",
+        "Network is unreachable",
+        "Operation timed out",
+        "The remote end hung up unexpectedly",
+        "early EOF",
+        "RPC failed",
+    ];
+
+    !AUTH.iter().any(|pattern| stderr.contains(pattern)) && NETWORK.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Sleeps for the exponential backoff before retry number `attempt`
+async fn wait_before_retry(attempt: u32, reason: &str, relative_path: &Path, args: &Args) {
+    let delay = Duration::from_secs(1 << (attempt - 1).min(6));
+    info!(
+        args,
+        "Network error in {:?} ({}), retrying in {}s (attempt {}/{})",
+        relative_path,
+        reason,
+        delay.as_secs(),
+        attempt + 1,
+        args.retries + 1
+    );
+    tokio::time::sleep(delay).await;
+}
+
+/// Remembers how many attempts a network operation took for the repo's result
+fn record_attempts(attempts: u32) {
+    let _ = REPO_STATE.try_with(|state| state.attempts.set(state.attempts.get().max(attempts)));
+}
+
+/// Warns and returns true when `--remote` names a remote the repo doesn't have
+fn is_missing_remote(path: &Path, relative_path: &Path, args: &Args) -> bool {
+    let Some(remote) = &args.remote else {
+        return false;
+    };
+    let exists = Repository::open(path).is_ok_and(|repo| repo.find_remote(remote).is_ok());
+    if !exists {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("No remote named '{}', skipping\n", remote);
+        print_with_prefix(&mut stdout, "Git", &message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+    }
+    !exists
+}
+
+/// `<remote> <branch>` arguments for `git pull` when `--remote` is given and a branch is checked out, empty otherwise
+fn remote_branch_args(path: &Path, args: &Args) -> Vec<String> {
+    let Some(remote) = &args.remote else {
+        return Vec::new();
+    };
+    let branch = Repository::open(path)
+        .ok()
+        .and_then(|repo| repo.head().ok().filter(|head| head.is_branch()).and_then(|head| head.shorthand().map(str::to_string)));
+    match branch {
+        Some(branch) => vec![remote.clone(), branch],
+        None => Vec::new(),
+    }
+}
+
+/// Names of the repo's remote-tracking refs
+fn remote_refs(path: &Path) -> HashSet<String> {
+    let Ok(repo) = Repository::open(path) else {
+        return HashSet::new();
+    };
+    let Ok(mut references) = repo.references_glob("refs/remotes/*") else {
+        return HashSet::new();
+    };
+    references.names().filter_map(|name| name.ok().map(str::to_string)).collect()
+}
+
+/// Counts the remote-tracking refs that disappeared since `refs_before` as pruned
+fn record_pruned(path: &Path, refs_before: &HashSet<String>) {
+    let refs_after = remote_refs(path);
+    let pruned = refs_before.difference(&refs_after).count();
+    let _ = REPO_STATE.try_with(|state| state.pruned.set(state.pruned.get() + pruned));
+}
+
+/// Fetches all remotes and reports how many commits arrived on the tracked branch
+async fn fetch_repo(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    info!(args, "Fetching repository at {:?}", relative_path);
+    if is_missing_remote(path, relative_path, args) {
+        return Outcome::Skipped;
+    }
+
+    let before = Repository::open(path).ok().and_then(|repo| upstream_oid(&repo));
+    let refs_before = args.prune.then(|| remote_refs(path));
+
+    let mut git_args = match &args.remote {
+        Some(remote) => vec!["fetch", remote.as_str()],
+        None => vec!["fetch", "--all"],
+    };
+    if args.prune {
+        git_args.push("--prune");
+    }
+    if args.tags {
+        git_args.push("--tags");
+    }
+    if run_git_with_retries(path, &git_args, "Git", relative_path, args).await.is_err() {
+        return Outcome::Failed;
+    }
+    if let Some(refs_before) = refs_before {
+        record_pruned(path, &refs_before);
+    }
+
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(_) => return Outcome::Succeeded,
+    };
+
+    let message = match (before, upstream_oid(&repo)) {
+        (Some(before), Some(after)) => {
+            let (new_commits, _) = repo.graph_ahead_behind(after, before).unwrap_or((0, 0));
+            format!("{} new commit(s) on upstream\n", new_commits)
+        }
+        (None, Some(_)) => "upstream fetched for the first time\n".to_string(),
+        _ => "no upstream\n".to_string(),
+    };
+
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, "Fetch", &message, Color::Green, relative_path, args.is_quiet()).unwrap();
+
+    Outcome::Succeeded
+}
+
+/// Pushes local commits to the upstream, skipping repos with nothing to push
+async fn push_repo(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    if is_missing_remote(path, relative_path, args) {
+        return Outcome::Skipped;
+    }
+    if let Some(remote) = &args.remote {
+        return push_to_remote(path, remote, relative_path, args).await;
+    }
+
+    let ahead = match Repository::open(path) {
+        Ok(repo) => ahead_behind(&repo).map(|(ahead, _)| ahead),
+        Err(e) => {
+            error!("Failed to open repository {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    };
+
+    match ahead {
+        None => {
+            info!(args, "No upstream configured for {:?}, skipping push", relative_path);
+            Outcome::Skipped
+        }
+        Some(0) => {
+            info!(args, "Nothing to push in {:?}", relative_path);
+            Outcome::Skipped
+        }
+        Some(ahead) => {
+            info!(args, "Pushing {} commit(s) in {:?}", ahead, relative_path);
+            if run_command(path, "git", &["push"], "Git", relative_path, args).await.is_err() {
+                error!("Push rejected for {:?}, the remote may have moved; pull and retry", relative_path);
+                return Outcome::Failed;
+            }
+            Outcome::Succeeded
+        }
+    }
+}
+
+/// Commits all changes in the working tree, then pushes the commit if asked to
+async fn commit_repo(path: &Path, message: &str, push: bool, relative_path: &Path, args: &Args) -> Outcome {
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    if args.dry_run {
+        let message = format!("Would commit all changes in {:?} with message {:?}\n", path, message);
+        print_with_prefix(&mut stdout, "Git commit", &message, Color::Cyan, relative_path, args.is_json()).unwrap();
+        return Outcome::Succeeded;
+    }
+
+    match commit_all(path, message) {
+        Ok(Some(oid)) => {
+            let message = format!("Committed {}\n", &oid.to_string()[..7]);
+            print_with_prefix(&mut stdout, "Git commit", &message, Color::Green, relative_path, args.is_json()).unwrap();
+        }
+        Ok(None) => {
+            info!(args, "Nothing to commit in {:?}", relative_path);
+            return Outcome::Skipped;
+        }
+        Err(e) => {
+            error!("Failed to commit in {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    }
+
+    if push {
+        return push_repo(path, relative_path, args).await;
+    }
+    Outcome::Succeeded
+}
+
+/// Stages every change, including deletions and untracked files, and commits it on HEAD;
+/// returns `None` without committing when the staged tree matches HEAD
+fn commit_all(path: &Path, message: &str) -> Result<Option<Oid>, git2::Error> {
+    let repo = Repository::open(path)?;
+    let mut index = repo.index()?;
+    // Staging everything here would commit the conflict markers and drop the merge's other parent
+    if repo.state() != RepositoryState::Clean || index.has_conflicts() {
+        let message = format!("repository is mid-operation ({:?}), finish or abort it first", repo.state());
+        return Err(git2::Error::from_str(&message));
+    }
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let unchanged = match &parent {
+        Some(parent) => parent.tree_id() == tree.id(),
+        None => tree.is_empty(),
+    };
+    if unchanged {
+        return Ok(None);
+    }
+
+    let signature = repo.signature()?;
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).map(Some)
+}
+
+/// Pushes the checked out branch to the same-named branch on the `--remote` remote
+async fn push_to_remote(path: &Path, remote: &str, relative_path: &Path, args: &Args) -> Outcome {
+    let (branch, ahead) = match Repository::open(path) {
+        Ok(repo) => {
+            let branch = current_branch(&repo);
+            let remote_oid = repo.refname_to_id(&format!("refs/remotes/{}/{}", remote, branch)).ok();
+            let local_oid = repo.head().ok().and_then(|head| head.target());
+            let ahead = match (local_oid, remote_oid) {
+                (Some(local), Some(remote)) => repo.graph_ahead_behind(local, remote).ok().map(|(ahead, _)| ahead),
+                // The branch doesn't exist on the remote yet, so everything is new
+                _ => None,
+            };
+            (branch, ahead)
+        }
+        Err(e) => {
+            error!("Failed to open repository {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    };
+
+    if ahead == Some(0) {
+        info!(args, "Nothing to push to {} in {:?}", remote, relative_path);
+        return Outcome::Skipped;
+    }
+
+    info!(args, "Pushing {} to {} in {:?}", branch, remote, relative_path);
+    if run_command(path, "git", &["push", remote, &branch], "Git", relative_path, args).await.is_err() {
+        error!("Push rejected for {:?}, the remote may have moved; pull and retry", relative_path);
+        return Outcome::Failed;
+    }
+    Outcome::Succeeded
+}
+
+/// Switches to `branch`, creating a tracking branch when it only exists on a remote
+async fn checkout_repo(path: &Path, branch: &str, relative_path: &Path, args: &Args) -> Outcome {
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            error!("Failed to open repository {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    };
+
+    if current_branch(&repo) == branch {
+        info!(args, "{:?} is already on {}", relative_path, branch);
+        return Outcome::Skipped;
+    }
+
+    let checkout_args = if repo.find_branch(branch, BranchType::Local).is_ok() {
+        vec!["checkout".to_string(), branch.to_string()]
+    } else if let Some(remote_branch) = find_remote_branch(&repo, branch, args.remote.as_deref()) {
+        vec!["checkout".to_string(), "--track".to_string(), remote_branch]
+    } else {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("No branch named '{}' locally or on a remote, skipping\n", branch);
+        print_with_prefix(&mut stdout, "Git", &message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    };
+    drop(repo);
+
+    let checkout_args: Vec<&str> = checkout_args.iter().map(String::as_str).collect();
+    if run_command(path, "git", &checkout_args, "Git", relative_path, args).await.is_err() {
+        error!("Failed to switch {:?} to {}", relative_path, branch);
+        return Outcome::Failed;
+    }
+
+    let _ = REPO_STATE.try_with(|state| state.branch.replace(Some(branch.to_string())));
+    info!(args, "Switched {:?} to {}", relative_path, branch);
+    Outcome::Succeeded
+}
+
+/// Creates `name` from `base` (or HEAD) and switches to it, skipping repos that already have it
+async fn create_branch(
+    path: &Path,
+    name: &str,
+    base: Option<&str>,
+    push: bool,
+    relative_path: &Path,
+    args: &Args,
+) -> Outcome {
+    let exists = match Repository::open(path) {
+        Ok(repo) => repo.find_branch(name, BranchType::Local).is_ok(),
+        Err(e) => {
+            error!("Failed to open repository {:?}: {}", relative_path, e);
+            record_error(RepoError::Git);
+            return Outcome::Failed;
+        }
+    };
+    if exists {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("Branch '{}' already exists, skipping\n", name);
+        print_with_prefix(&mut stdout, "Git", &message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    }
+
+    let mut git_args = vec!["checkout", "-b", name];
+    git_args.extend(base);
+    if run_command(path, "git", &git_args, "Git", relative_path, args).await.is_err() {
+        error!("Failed to create branch {} in {:?}", name, relative_path);
+        return Outcome::Failed;
+    }
+    let _ = REPO_STATE.try_with(|state| state.branch.replace(Some(name.to_string())));
+
+    if push {
+        let remote = args.remote.as_deref().unwrap_or("origin");
+        if run_git_with_retries(path, &["push", "-u", remote, name], "Git", relative_path, args).await.is_err() {
+            error!("Created branch {} in {:?}, but pushing it to {} failed", name, relative_path, remote);
+            return Outcome::Failed;
+        }
+    }
+    Outcome::Succeeded
+}
+
+/// Finds `<remote>/<branch>` among the remote-tracking branches, preferring `preferred` and then origin
+fn find_remote_branch(repo: &Repository, branch: &str, preferred: Option<&str>) -> Option<String> {
+    let remotes = repo.remotes().ok()?;
+    let mut names: Vec<&str> = remotes.iter().flatten().collect();
+    names.sort_by_key(|&name| (Some(name) != preferred, name != "origin"));
+
+    names
+        .into_iter()
+        .map(|remote| format!("{}/{}", remote, branch))
+        .find(|remote_branch| repo.find_branch(remote_branch, BranchType::Remote).is_ok())
+}
+
+/// Runs `git gc` and reports how much smaller the repo's git directory got
+async fn gc_repo(path: &Path, git_dir: &Path, aggressive: bool, relative_path: &Path, args: &Args) -> Outcome {
+    let mut gc_args = vec!["gc"];
+    if aggressive {
+        gc_args.push("--aggressive");
+    }
+
+    let size_before = directory_size(git_dir);
+    if run_command(path, "git", &gc_args, "Git gc", relative_path, args).await.is_err() {
+        return Outcome::Failed;
+    }
+    if args.dry_run {
+        return Outcome::Succeeded;
+    }
+
+    // git gc doesn't say what it freed, so compare the size of the git directory instead
+    let reclaimed = size_before.saturating_sub(directory_size(git_dir));
+    let message = format!("Reclaimed {:.1} MB\n", reclaimed as f64 / 1_000_000.0);
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, "Git gc", &message, Color::Green, relative_path, args.is_quiet()).unwrap();
+    Outcome::Succeeded
+}
+
+/// Describes what resetting to the upstream would throw away: local commits missing from the upstream,
+/// then uncommitted changes and, when cleaning, untracked files; `None` if HEAD has no upstream
+fn discarded_by_reset(repo: &Repository, clean: bool) -> Option<Vec<String>> {
+    let upstream = upstream_oid(repo)?;
+    let head = repo.head().ok()?.target()?;
+    let mut discarded = Vec::new();
+
+    let mut walk = repo.revwalk().ok()?;
+    walk.push(head).ok()?;
+    walk.hide(upstream).ok()?;
+    for oid in walk.flatten() {
+        let summary = repo.find_commit(oid).ok().and_then(|commit| commit.summary().map(str::to_string));
+        discarded.push(format!("commit {} {}", &oid.to_string()[..7], summary.unwrap_or_default()));
+    }
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(clean).include_ignored(false);
+    for entry in repo.statuses(Some(&mut options)).ok()?.iter() {
+        let status = entry.status();
+        let kind = if status.contains(Status::WT_NEW) {
+            "untracked"
+        } else if status.intersects(Status::INDEX_DELETED | Status::WT_DELETED) {
+            "deleted"
+        } else if status.contains(Status::INDEX_NEW) {
+            "added"
+        } else {
+            "modified"
+        };
+        discarded.push(format!("{} {}", kind, entry.path().unwrap_or("?")));
+    }
+    Some(discarded)
+}
+
+/// Prints what will be lost, then runs `git reset --hard @{upstream}` and, if asked, `git clean -fd`
+async fn reset_repo(path: &Path, discarded: Option<Vec<String>>, clean: bool, relative_path: &Path, args: &Args) -> Outcome {
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    let Some(discarded) = discarded else {
+        let message = "No upstream to reset to (detached HEAD or untracked branch), skipping\n";
+        print_with_prefix(&mut stdout, "Reset", message, Color::Yellow, relative_path, args.is_quiet()).unwrap();
+        return Outcome::Skipped;
+    };
+
+    // The list is printed even under --quiet, since it is the record of what was thrown away
+    if discarded.is_empty() {
+        info!(args, "Nothing to discard in {:?}", relative_path);
+    }
+    for item in &discarded {
+        let message = format!("Discarding {}\n", item);
+        print_with_prefix(&mut stdout, "Reset", &message, Color::Yellow, relative_path, args.is_json()).unwrap();
+    }
+
+    if run_command(path, "git", &["reset", "--hard", "@{upstream}"], "Git reset", relative_path, args).await.is_err() {
+        return Outcome::Failed;
+    }
+    if clean && run_command(path, "git", &["clean", "-fd"], "Git clean", relative_path, args).await.is_err() {
+        return Outcome::Failed;
+    }
+    Outcome::Succeeded
+}
+
+/// Runs a user supplied command in the repository
+async fn exec_repo(path: &Path, command: &[String], relative_path: &Path, args: &Args) -> bool {
+    let Some((program, rest)) = command.split_first() else {
+        return false;
+    };
+    let command_args: Vec<&str> = rest.iter().map(String::as_str).collect();
+
+    run_command(path, program, &command_args, program, relative_path, args).await.is_ok()
+}
+
+/// Runs the audit tool of every detected ecosystem; vulnerabilities fail the repo
+async fn audit_repo(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    let mut outcome = Outcome::Skipped;
+
+    if path.join("Cargo.lock").exists() {
+        info!(args, "Auditing Rust dependencies in {:?}", relative_path.join("Cargo.lock"));
+
+        let audited = match run_command(path, "cargo", &["audit"], "Cargo audit", relative_path, args).await {
+            Ok(()) => Outcome::Succeeded,
+            Err(CommandError::Failed(stderr)) if stderr.contains("no such command") => {
+                error!("cargo-audit is not installed, skipping {:?} (cargo install cargo-audit)", relative_path);
+                Outcome::Skipped
+            }
+            Err(CommandError::NotFound) => Outcome::Skipped,
+            Err(_) => {
+                error!("Vulnerable Rust dependencies found in {:?}", relative_path);
+                Outcome::Failed
+            }
+        };
+        outcome = outcome.and(audited);
+    }
+
+    let node_audit: Option<(&str, &[&str])> = if path.join("pnpm-lock.yaml").exists() {
+        Some(("pnpm", &["audit", "--json"]))
+    } else if path.join("yarn.lock").exists() && path.join(".yarnrc.yml").exists() {
+        Some(("yarn", &["npm", "audit", "--json"]))
+    } else if path.join("yarn.lock").exists() {
+        Some(("yarn", &["audit", "--json"]))
+    } else if path.join("package-lock.json").exists() {
+        Some(("npm", &["audit", "--json"]))
+    } else {
+        None
+    };
+    if let Some((tool, audit_args)) = node_audit {
+        info!(args, "Auditing Node dependencies in {:?} with {}", relative_path, tool);
+        outcome = outcome.and(audit_node(path, tool, audit_args, relative_path, args).await);
+    }
+
+    if outcome == Outcome::Skipped {
+        info!(args, "Nothing to audit in {:?}", relative_path);
+    }
+    outcome
+}
+
+/// Vulnerability counts by severity from an audit report
+#[derive(Default)]
+struct Vulnerabilities {
+    low: u64,
+    moderate: u64,
+    high: u64,
+    critical: u64,
+}
+
+impl Vulnerabilities {
+    fn total(&self) -> u64 {
+        self.low + self.moderate + self.high + self.critical
+    }
+
+    /// Adds counts from a `{"low": n, "moderate": n, ...}` object as npm and pnpm report them
+    fn add_counts(&mut self, counts: &serde_json::Value) {
+        let count = |severity: &str| counts.get(severity).and_then(serde_json::Value::as_u64).unwrap_or(0);
+        self.low += count("low");
+        self.moderate += count("moderate");
+        self.high += count("high");
+        self.critical += count("critical");
+    }
+
+    /// Counts a single advisory by its severity name
+    fn add_severity(&mut self, severity: &str) {
+        match severity.to_ascii_lowercase().as_str() {
+            "low" => self.low += 1,
+            "moderate" => self.moderate += 1,
+            "high" => self.high += 1,
+            "critical" => self.critical += 1,
+            _ => {}
+        }
+    }
+
+    /// Reads npm and pnpm's single JSON report, or yarn's newline delimited one
+    fn parse(report: &str) -> Option<Vulnerabilities> {
+        let mut vulnerabilities = Vulnerabilities::default();
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(report) {
+            vulnerabilities.add_counts(value.pointer("/metadata/vulnerabilities")?);
+            return Some(vulnerabilities);
+        }
+
+        let mut parsed = false;
+        for line in report.lines() {
+            let Ok(value) = serde_json::from_str::<serde_json::Value>(line) else {
+                continue;
+            };
+            parsed = true;
+            if value["type"] == "auditSummary" {
+                vulnerabilities.add_counts(&value["data"]["vulnerabilities"]);
+            } else if let Some(severity) = value.pointer("/children/Severity").and_then(serde_json::Value::as_str) {
+                vulnerabilities.add_severity(severity);
+            }
+        }
+        parsed.then_some(vulnerabilities)
+    }
+}
+
+/// Runs a Node package manager's audit and prints the vulnerability tally by severity
+async fn audit_node(path: &Path, tool: &str, audit_args: &[&str], relative_path: &Path, args: &Args) -> Outcome {
+    let prefix = format!("{} audit", tool);
+    if args.dry_run {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("Would run `{}` in {:?}\n", command_line(tool, audit_args, args), path);
+        print_with_prefix(&mut stdout, &prefix, &message, Color::Cyan, relative_path, args.is_json()).unwrap();
+        return Outcome::Succeeded;
+    }
+
+    // Audit tools exit non-zero whenever they find something, so the report decides the outcome
+    let output = match run_captured(path, tool, audit_args, relative_path, args).await {
+        Ok(output) => output,
+        Err(CommandError::NotFound) => return Outcome::Skipped,
+        Err(_) => return Outcome::Failed,
+    };
+
+    let report = String::from_utf8_lossy(&output.stdout);
+    // yarn prints nothing at all when there is nothing to report
+    let clean = output.status.success() && report.trim().is_empty();
+    let Some(vulnerabilities) = (if clean { Some(Vulnerabilities::default()) } else { Vulnerabilities::parse(&report) }) else {
+        error!("Could not read the {} audit report for {:?}", tool, relative_path);
+        return Outcome::Failed;
+    };
+
+    let message = format!(
+        "low {}, moderate {}, high {}, critical {}\n",
+        vulnerabilities.low, vulnerabilities.moderate, vulnerabilities.high, vulnerabilities.critical
+    );
+    let vulnerable = vulnerabilities.total() > 0;
+    let color = if vulnerable { Color::Red } else { Color::Green };
+    let mut stdout = StandardStream::stdout(args.color_choice());
+    print_with_prefix(&mut stdout, &prefix, &message, color, relative_path, args.is_quiet() && !vulnerable).unwrap();
+
+    if vulnerable {
+        Outcome::Failed
+    } else {
+        Outcome::Succeeded
+    }
+}
+
+/// Formats a command as a shell would show it, preceded by the `--env` assignments it runs with
+fn command_line(command: &str, command_args: &[&str], args: &Args) -> String {
+    let assignments = args.env.iter().map(|(key, value)| format!("{}={}", key, value));
+    let words: Vec<String> = assignments
+        .chain(std::iter::once(command.to_string()))
+        .chain(command_args.iter().map(|arg| arg.to_string()))
+        .collect();
+    words.join(" ")
+}
+
+/// Parses a `KEY=VALUE` environment assignment given to `--env`
+fn parse_env_var(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {:?}", assignment)),
+    }
+}
+
+/// Reads `KEY=VALUE` lines from a dotenv-style file, ignoring blank lines, comments and a leading `export`
+fn load_env_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut vars = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = parse_env_var(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        let value = value.trim();
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(value);
+        vars.push((key.trim().to_string(), unquoted.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Runs a command without echoing its output, returning it for parsing instead
+async fn run_captured(
+    path: &Path,
+    command: &str,
+    args: &[&str],
+    relative_path: &Path,
+    opts: &Args,
+) -> Result<std::process::Output, CommandError> {
+    let started = Instant::now();
+    let output = Command::new(command)
+        .args(args)
+        .envs(opts.env.iter().map(|(key, value)| (key, value)))
+        .current_dir(path)
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match output {
+        Ok(output) => {
+            tracing::info!(
+                repo = %relative_path.display(),
+                command = %format!("{} {}", command, args.join(" ")).trim_end(),
+                exit_code = output.status.code(),
+                duration_secs = started.elapsed().as_secs_f64(),
+                "command finished"
+            );
+            Ok(output)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            error!("{} not found on PATH, skipping in {:?}", command, relative_path);
+            record_error(RepoError::MissingTool);
+            Err(CommandError::NotFound)
+        }
+        Err(e) => {
+            error!("Failed to start {} in {:?}: {}", command, relative_path, e);
+            record_error(RepoError::Io);
+            Err(CommandError::Failed(String::new()))
+        }
+    }
+}
+
+/// Reports outdated dependencies for every detected ecosystem without modifying anything
+async fn outdated_repo(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    let mut checks: Vec<(&str, &str, Vec<&str>, Report)> = Vec::new();
+
+    if path.join("Cargo.lock").exists() {
+        checks.push(("Cargo", "cargo", vec!["outdated", "--root-deps-only", "--format", "json"], Report::Cargo));
+    }
+    if path.join("pnpm-lock.yaml").exists() {
+        checks.push(("pnpm", "pnpm", vec!["outdated", "--format", "json"], Report::Npm));
+    } else if path.join("yarn.lock").exists() && !path.join(".yarnrc.yml").exists() {
+        // Yarn 2+ has no outdated command, only the interactive upgrader
+        checks.push(("Yarn", "yarn", vec!["outdated", "--json"], Report::Yarn));
+    } else if path.join("package-lock.json").exists() {
+        checks.push(("npm", "npm", vec!["outdated", "--json"], Report::Npm));
+    }
+    if path.join("go.mod").exists() {
+        checks.push(("Go", "go", vec!["list", "-u", "-m", "-json", "all"], Report::Go));
+    }
+    if path.join("Gemfile.lock").exists() {
+        checks.push(("Bundler", "bundle", vec!["outdated", "--parseable"], Report::Bundler));
+    }
+    if path.join("composer.lock").exists() {
+        checks.push(("Composer", "composer", vec!["outdated", "--direct", "--format=json"], Report::Composer));
+    }
+    if !files_with_extension(path, "sln").is_empty() || !files_with_extension(path, "csproj").is_empty() {
+        checks.push(("dotnet", "dotnet", vec!["list", "package", "--outdated"], Report::Dotnet));
+    }
+    if path.join("requirements.txt").exists() {
+        checks.push(("pip", "pip", vec!["list", "--outdated", "--format", "json"], Report::Pip));
+    }
 
+    if checks.is_empty() {
+        info!(args, "No dependencies to check in {:?}", relative_path);
+        return Outcome::Skipped;
+    }
 
-#[tokio::main]
-async fn main() {
-    println!("MetaZeta");
+    let mut outcome = Outcome::Skipped;
+    for (prefix, tool, tool_args, report) in checks {
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        if args.dry_run {
+            let message = format!("Would run `{}` in {:?}\n", command_line(tool, &tool_args, args), path);
+            print_with_prefix(&mut stdout, prefix, &message, Color::Cyan, relative_path, args.is_json()).unwrap();
+            outcome = outcome.and(Outcome::Succeeded);
+            continue;
+        }
 
-    let args = Args::parse();
-    let base_path = Path::new(&args.path);
+        // Like the audit tools, several of these exit non-zero just because something is outdated
+        let output = match run_captured(path, tool, &tool_args, relative_path, args).await {
+            Ok(output) => output,
+            Err(CommandError::NotFound) => continue,
+            Err(_) => {
+                outcome = Outcome::Failed;
+                continue;
+            }
+        };
+
+        if output.stdout.is_empty() && !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if tool == "cargo" && stderr.contains("no such command") {
+                error!("cargo-outdated is not installed, skipping {:?} (cargo install cargo-outdated)", relative_path);
+                continue;
+            }
+            error!("{} failed in {:?}: {}", tool, relative_path, stderr.trim());
+            outcome = Outcome::Failed;
+            continue;
+        }
 
+        let Some(packages) = report.parse(&String::from_utf8_lossy(&output.stdout)) else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("Could not read the {} report for {:?}: {}", tool, relative_path, stderr.trim());
+            outcome = Outcome::Failed;
+            continue;
+        };
 
-    process_repositories(base_path, &args.action).await;
+        let color = if packages.is_empty() { Color::Green } else { Color::Yellow };
+        let message = format!("{} package(s) behind\n", packages.len());
+        print_with_prefix(&mut stdout, prefix, &message, color, relative_path, args.is_quiet()).unwrap();
+        if args.verbose {
+            for package in &packages {
+                let line = format!("  {} {} -> {}\n", package.name, package.current, package.latest);
+                print_with_prefix(&mut stdout, prefix, &line, color, relative_path, args.is_quiet()).unwrap();
+            }
+        }
+        outcome = outcome.and(Outcome::Succeeded);
+    }
+    outcome
 }
 
+/// Updates dependencies based on lockfiles
+async fn update_dependencies(path: &Path, relative_path: &Path, args: &Args) -> Outcome {
+    info!(args, "Updating dependencies for {:?}", relative_path);
+
+    let mut updated = false;
+    let mut installs: Vec<(&str, BoxFuture<bool>)> = Vec::new();
+
+    // Commands configured in `[managers]` replace the built-in handling of their lockfile
+    for (lockfile, template) in &args.managers {
+        if !path.join(lockfile).exists() {
+            continue;
+        }
+        info!(args, "Detected {} in {:?}, running `{}` from the config", lockfile, relative_path, template);
+
+        let mut words = template.split_whitespace();
+        let program = words.next().expect("Manager commands are checked to be non-empty when the config is loaded");
+        let command_args: Vec<&str> = words.collect();
+        let install = async move { run_manager(path, program, &command_args, program, relative_path, args).await };
+        installs.push((lockfile.as_str(), install.boxed()));
+        updated = true;
+    }
+    let detected = |file: &str| path.join(file).exists() && !args.managers.contains_key(file);
+
+    // Check for Node.js lockfiles; each one found is installed, so mixed repos get every manager run,
+    // unless `--manager` picks the one tool to use for any package.json
+    let has_package_json = path.join("package.json").exists();
+    let js_workspace = REPO_STATE.try_with(|state| state.js_workspace.borrow().clone()).ok().flatten();
+    if let (Some(root), true) = (&js_workspace, has_package_json) {
+        info!(args, "{:?} is part of the JS workspace at {:?}, leaving its Node install to the root", relative_path, root);
+    } else if let (Some(manager), true) = (args.manager, has_package_json) {
+        info!(args, "Using {} for the Node dependencies in {:?} as requested by --manager", manager.name(), relative_path);
+    }
+    let node = |lockfile: &str, manager: NodeManager| match args.manager {
+        _ if js_workspace.is_some() => false,
+        Some(forced) => forced == manager && has_package_json,
+        None => detected(lockfile),
+    };
+    if node("bun.lockb", NodeManager::Bun) || node("bun.lock", NodeManager::Bun) {
+        info!(args, "Detected Bun dependencies in {:?}", relative_path);
 
-async fn process_repositories(base_path: &Path, action: &Option<Action>) {
-    let (tx, mut rx) = mpsc::channel(32);
+        let bun_args: &[&str] = if args.frozen {
+            &["install", "--frozen-lockfile"]
+        } else if args.is_upgrade() {
+            &["update"]
+        } else {
+            &["install"]
+        };
+        installs.push(("Bun", run_manager(path, "bun", bun_args, "Bun", relative_path, args).boxed()));
+        updated = true;
+    }
 
-    for entry in WalkDir::new(base_path).into_iter().filter_map(|e| e.ok()) {
+    if node("package-lock.json", NodeManager::Npm) {
+        info!(
+            args,
+            "Detected npm dependencies in {:?}",
 
+            relative_path.join("package-lock.json")
+        );
 
 
-        let path = entry.path().to_owned();
-        if is_git_repo(&path) {
-            let tx = tx.clone();
-            let action = action.clone();
-            tokio::spawn(async move {
-                let relative_path = path.strip_prefix(base_path).unwrap_or(&path);
-                process_repository(&path, &action, relative_path).await;
-                tx.send(()).await.unwrap();
-            });
-        }
+        let npm_command = if args.frozen { "ci" } else { "install" };
+        let install = async move { run_manager(path, "npm", &[npm_command], "npm", relative_path, args).await };
+        installs.push(("npm", install.boxed()));
+        updated = true;
     }
 
-    drop(tx);
+    if node("yarn.lock", NodeManager::Yarn) {
 
-    while rx.recv().await.is_some() {}
-}
 
+        info!(args, "Detected Yarn dependencies in {:?}", relative_path.join("yarn.lock"));
+
+        let yarn_args: &[&str] = if args.frozen { &["install", "--frozen-lockfile"] } else { &["install"] };
+        installs.push(("Yarn", run_manager(path, "yarn", yarn_args, "Yarn", relative_path, args).boxed()));
+        updated = true;
+    }
 
-async fn process_repository(path: &Path, action: &Option<Action>, relative_path: &Path) {
-    let full_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if node("pnpm-lock.yaml", NodeManager::Pnpm) {
+        info!(
+            args,
+            "Detected pnpm dependencies in {:?}",
 
-    println!("Found repository: {:?}", relative_path);
+            relative_path.join("pnpm-lock.yaml")
+        );
 
-    match action {
 
-        Some(Action::Pull) => pull_repo(&full_path, relative_path).await,
-        Some(Action::Update) => {
+        let pnpm_args: &[&str] = if args.frozen { &["install", "--frozen-lockfile"] } else { &["install"] };
+        installs.push(("pnpm", run_manager(path, "pnpm", pnpm_args, "pnpm", relative_path, args).boxed()));
+        updated = true;
+    }
 
+    // Check for Deno, independently of Node since Deno repos often carry a package.json as well
+    if ["deno.lock", "deno.json", "deno.jsonc"].iter().any(|file| detected(file)) {
+        info!(args, "Detected Deno dependencies in {:?}", relative_path);
 
-            pull_repo(&full_path, relative_path).await;
-            update_dependencies(&full_path, relative_path).await;
+        let mut deno_args = vec![if args.deno_entry.is_some() { "cache" } else { "install" }];
+        if args.is_upgrade() {
+            deno_args.push("--reload");
         }
-        None => {
+        if args.frozen {
+            deno_args.push("--frozen");
+        }
+        if let Some(entry) = &args.deno_entry {
+            deno_args.push(entry);
+        }
+        let install = async move { run_manager(path, "deno", &deno_args, "Deno", relative_path, args).await };
+        installs.push(("Deno", install.boxed()));
+        updated = true;
+    }
 
+    // Check for Rust lockfile; a workspace updates every member from the one lockfile at its root
+    let cargo_workspace = REPO_STATE.try_with(|state| state.cargo_workspace.borrow().clone()).ok().flatten();
+    if let (Some(root), true) = (&cargo_workspace, detected("Cargo.lock")) {
+        info!(args, "{:?} is a member of the Cargo workspace at {:?}, leaving its update to the root", relative_path, root);
+    } else if detected("Cargo.lock") {
+        if is_cargo_workspace_root(path) {
+            info!(args, "Detected Rust workspace in {:?}", relative_path.join("Cargo.toml"));
+        } else {
+            info!(
+                args,
+                "Detected Rust dependencies in {:?}",
 
-            pull_repo(&full_path, relative_path).await;
-            update_dependencies(&full_path, relative_path).await;
+                relative_path.join("Cargo.lock")
+            );
         }
+
+
+        let cargo_args: &[&str] = if args.frozen { &["fetch", "--locked"] } else { &["update"] };
+        installs.push(("Cargo", run_manager(path, "cargo", cargo_args, "Cargo", relative_path, args).boxed()));
+        updated = true;
     }
-}
 
-/// Checks if a directory is a Git repository
-fn is_git_repo(path: &Path) -> bool {
-    Repository::open(path).is_ok()
-}
+    // Check for Go modules, syncing a workspace once from its root
+    if detected("go.work") {
+        info!(args, "Detected Go workspace in {:?}", relative_path.join("go.work"));
 
-/// Pulls the latest changes in the repository
+        let go_args: &[&str] = if args.frozen { &["mod", "download"] } else { &["work", "sync"] };
+        installs.push(("Go", run_manager(path, "go", go_args, "Go", relative_path, args).boxed()));
+        updated = true;
+    } else if detected("go.mod") {
+        info!(args, "Detected Go dependencies in {:?}", relative_path.join("go.mod"));
 
+        let go_args: &[&str] = match args.go_command {
+            _ if args.frozen => &["mod", "download"],
+            GoCommand::Tidy => &["mod", "tidy"],
+            GoCommand::Get => &["get", "-u", "./..."],
+        };
+        installs.push(("Go", run_manager(path, "go", go_args, "Go", relative_path, args).boxed()));
+        updated = true;
+    }
 
+    // Check for Ruby lockfile
+    if detected("Gemfile.lock") {
+        info!(args, "Detected Bundler dependencies in {:?}", relative_path.join("Gemfile.lock"));
 
-async fn pull_repo(path: &Path, relative_path: &Path) {
-    println!("Pulling repository at {:?}", relative_path);
-    run_command(path, "git", &["pull"], "Git", relative_path).await;
-}
+        let bundle_command = if args.is_upgrade() { "update" } else { "install" };
+        // `bundle install --frozen` is deprecated, so the lockfile is enforced through Bundler's setting instead
+        let mut bundle_opts = Cow::Borrowed(args);
+        if args.frozen {
+            bundle_opts.to_mut().env.push(("BUNDLE_FROZEN".to_string(), "true".to_string()));
+        }
+        let install = async move { run_manager(path, "bundle", &[bundle_command], "Bundler", relative_path, &bundle_opts).await };
+        installs.push(("Bundler", install.boxed()));
+        updated = true;
+    }
 
-/// Updates dependencies based on lockfiles
+    // Check for PHP Composer manifest
+    if detected("composer.lock") || detected("composer.json") {
+        info!(args, "Detected Composer dependencies in {:?}", relative_path);
 
+        let composer_command = if args.is_upgrade() { "update" } else { "install" };
+        let install = async move { run_manager(path, "composer", &[composer_command], "Composer", relative_path, args).await };
+        installs.push(("Composer", install.boxed()));
+        updated = true;
+    }
 
-async fn update_dependencies(path: &Path, relative_path: &Path) {
-    println!("Updating dependencies for {:?}", relative_path);
+    // Check for Maven project; only the root pom is considered so module poms are never run on their own
+    if detected("pom.xml") {
+        info!(args, "Detected Maven dependencies in {:?}", relative_path.join("pom.xml"));
 
-    let mut updated = false;
+        let maven_goal = if args.is_upgrade() { "versions:use-latest-releases" } else { "dependency:resolve" };
+        let install = async move { run_manager(path, "mvn", &["-q", maven_goal], "Maven", relative_path, args).await };
+        installs.push(("Maven", install.boxed()));
+        updated = true;
+    }
 
-    // Check for Node.js lockfiles
-    if path.join("package-lock.json").exists() {
-        println!(
-            "Detected npm dependencies in {:?}",
+    // Check for Gradle build, preferring the project's wrapper
+    if ["build.gradle", "build.gradle.kts", "gradle.lockfile"].iter().any(|file| detected(file)) {
+        info!(args, "Detected Gradle dependencies in {:?}", relative_path);
 
-            relative_path.join("package-lock.json")
-        );
+        let wrapper = path.join(if cfg!(windows) { "gradlew.bat" } else { "gradlew" });
+        let gradle = if is_executable(&wrapper) {
+            wrapper.to_string_lossy().into_owned()
+        } else {
+            "gradle".to_string()
+        };
+        let gradle_args: &[&str] = if args.is_upgrade() {
+            &["dependencies", "--write-locks"]
+        } else {
+            &["dependencies"]
+        };
+        let install = async move { run_manager(path, &gradle, gradle_args, "Gradle", relative_path, args).await };
+        installs.push(("Gradle", install.boxed()));
+        updated = true;
+    }
 
+    // Check for .NET projects, restoring through every solution when there are any, since each can list different projects
+    let solutions = files_with_extension(path, "sln");
+    let projects = files_with_extension(path, "csproj");
+    if !solutions.is_empty() || !projects.is_empty() || detected("packages.lock.json") {
+        info!(args, "Detected NuGet dependencies in {:?}", relative_path);
 
-        run_command(path, "npm", &["install"], "npm", relative_path).await;
+        let targets = if solutions.is_empty() { projects } else { solutions };
+        let locked_mode: &[&str] = if args.frozen { &["--locked-mode"] } else { &[] };
+        let install = async move {
+            if targets.is_empty() {
+                let restore_args = [&["restore"], locked_mode].concat();
+                return run_manager(path, "dotnet", &restore_args, "dotnet", relative_path, args).await;
+            }
+            let mut success = true;
+            for target in &targets {
+                let restore_args = [&["restore", target.as_str()], locked_mode].concat();
+                success &= run_manager(path, "dotnet", &restore_args, "dotnet", relative_path, args).await;
+            }
+            success
+        };
+        installs.push(("dotnet", install.boxed()));
         updated = true;
-    } else if path.join("yarn.lock").exists() {
+    }
+
+    // Check for Elixir lockfile
+    if detected("mix.lock") {
+        info!(args, "Detected Mix dependencies in {:?}", relative_path.join("mix.lock"));
 
+        let mix_args: &[&str] = if args.frozen {
+            &["deps.get", "--check-locked"]
+        } else if args.is_upgrade() {
+            &["deps.update", "--all"]
+        } else {
+            &["deps.get"]
+        };
+        let install = async move {
+            let success = run_manager(path, "mix", mix_args, "Mix", relative_path, args).await;
+            if !success {
+                error!("Mix failed in {:?}; if Hex is missing, install it with `mix local.hex`", relative_path);
+            }
+            success
+        };
+        installs.push(("Mix", install.boxed()));
+        updated = true;
+    }
 
-        println!("Detected Yarn dependencies in {:?}", relative_path.join("yarn.lock"));
+    // Check for Dart or Flutter packages
+    if detected("pubspec.lock") || detected("pubspec.yaml") {
+        info!(args, "Detected pub dependencies in {:?}", relative_path);
 
-        run_command(path, "yarn", &["install"], "Yarn", relative_path).await;
+        let sdk = if is_flutter_project(path) { "flutter" } else { "dart" };
+        let pub_args: &[&str] = if args.frozen {
+            &["pub", "get", "--enforce-lockfile"]
+        } else if args.is_upgrade() {
+            &["pub", "upgrade"]
+        } else {
+            &["pub", "get"]
+        };
+        installs.push(("pub", run_manager(path, sdk, pub_args, "pub", relative_path, args).boxed()));
         updated = true;
-    } else if path.join("pnpm-lock.yaml").exists() {
-        println!(
-            "Detected pnpm dependencies in {:?}",
+    }
 
-            relative_path.join("pnpm-lock.yaml")
-        );
+    // Check for Swift packages; Xcode projects keep a Package.resolved without a manifest, so require Package.swift
+    if detected("Package.swift") {
+        info!(args, "Detected Swift Package Manager dependencies in {:?}", relative_path.join("Package.swift"));
+
+        let swift_args: &[&str] = if args.frozen {
+            &["package", "resolve", "--force-resolved-versions"]
+        } else if args.is_upgrade() {
+            &["package", "update"]
+        } else {
+            &["package", "resolve"]
+        };
+        installs.push(("SPM", run_manager(path, "swift", swift_args, "SPM", relative_path, args).boxed()));
+        updated = true;
+    }
 
+    // Check for Conda environment
+    if detected("environment.yml") {
+        info!(args, "Detected Conda environment in {:?}", relative_path.join("environment.yml"));
 
-        run_command(path, "pnpm", &["install"], "pnpm", relative_path).await;
+        let env_name = args.conda_env.clone().or_else(|| conda_env_name(&path.join("environment.yml")));
+        let install = async move {
+            let mut conda_args = vec!["env", "update", "-f", "environment.yml"];
+            if let Some(env_name) = &env_name {
+                conda_args.extend(["-n", env_name]);
+            }
+            run_manager(path, "conda", &conda_args, "Conda", relative_path, args).await
+        };
+        installs.push(("Conda", install.boxed()));
         updated = true;
     }
 
-    // Check for Rust lockfile
-    if path.join("Cargo.lock").exists() {
-        println!(
-            "Detected Rust dependencies in {:?}",
+    // Check for Nix flake; updating rewrites flake.lock so it only happens for the update action
+    if detected("flake.nix") || detected("flake.lock") {
+        info!(args, "Detected Nix flake in {:?}", relative_path);
 
-            relative_path.join("Cargo.lock")
-        );
+        // A dry run shows the planned command whether or not the tool is installed, as for every other manager
+        if !args.dry_run && !command_exists("nix") {
+            error!("nix not found on PATH, skipping Nix in {:?}", relative_path);
+        } else if args.is_upgrade() {
+            let install = run_manager(path, "nix", &["flake", "update"], "Nix", relative_path, args);
+            installs.push(("Nix", install.boxed()));
+        }
+        updated = true;
+    }
 
+    // Check for uv first, since its pyproject.toml would otherwise look like a plain pip project
+    if detected("uv.lock") {
+        info!(args, "Detected uv dependencies in {:?}", relative_path.join("uv.lock"));
 
-        run_command(path, "cargo", &["update"], "Cargo", relative_path).await;
+        if args.dry_run || command_exists("uv") {
+            let uv_args: &[&str] = if args.frozen {
+                &["sync", "--frozen"]
+            } else if args.is_upgrade() {
+                &["lock", "--upgrade"]
+            } else {
+                &["sync"]
+            };
+            installs.push(("uv", run_manager(path, "uv", uv_args, "uv", relative_path, args).boxed()));
+        } else {
+            error!("uv not found on PATH, skipping uv in {:?}", relative_path);
+        }
         updated = true;
     }
 
     // Check for Python lockfiles
-    if path.join("Pipfile").exists() {
+    if detected("Pipfile") {
 
 
-        println!("Detected Pipenv dependencies in {:?}", relative_path.join("Pipfile"));
+        info!(args, "Detected Pipenv dependencies in {:?}", relative_path.join("Pipfile"));
 
-        run_command(path, "pipenv", &["install"], "Pipenv", relative_path).await;
+        let pipenv_args: &[&str] = if args.frozen { &["install", "--deploy"] } else { &["install"] };
+        installs.push(("Pipenv", run_manager(path, "pipenv", pipenv_args, "Pipenv", relative_path, args).boxed()));
         updated = true;
-    } else if path.join("poetry.lock").exists() {
-        println!(
+    }
+
+    if detected("poetry.lock") {
+        info!(
+            args,
             "Detected Poetry dependencies in {:?}",
 
             relative_path.join("poetry.lock")
         );
 
 
-        run_command(path, "poetry", &["update"], "Poetry", relative_path).await;
+        let poetry_command = if args.frozen { "install" } else { "update" };
+        let install = async move { run_manager(path, "poetry", &[poetry_command], "Poetry", relative_path, args).await };
+        installs.push(("Poetry", install.boxed()));
         updated = true;
-    } else if path.join("requirements.txt").exists() {
-        println!(
-            "Detected pip dependencies in {:?}",
+    }
 
-            relative_path.join("requirements.txt")
-        );
+    // PEP 621 and Poetry projects that keep no lockfile are installed from pyproject.toml itself
+    let python_locked = ["uv.lock", "poetry.lock", "Pipfile", "Pipfile.lock"].iter().any(|file| path.join(file).exists());
+    if detected("pyproject.toml") && !python_locked {
+        match pyproject_kind(&path.join("pyproject.toml")) {
+            Some(PyProject::Poetry) => {
+                info!(args, "Detected Poetry project in {:?}", relative_path.join("pyproject.toml"));
+
+                let poetry_command = if args.is_upgrade() { "update" } else { "install" };
+                let install = async move { run_manager(path, "poetry", &[poetry_command], "Poetry", relative_path, args).await };
+                installs.push(("Poetry", install.boxed()));
+                updated = true;
+            }
+            Some(PyProject::Pep621) => {
+                info!(args, "Detected Python project in {:?}", relative_path.join("pyproject.toml"));
 
+                let pip_args: &[&str] = &["install", "-e", "."];
+                installs.push(("pip", run_manager(path, "pip", pip_args, "pip", relative_path, args).boxed()));
+                updated = true;
+            }
+            None => {}
+        }
+    }
 
-        run_command(path, "pip", &["install", "-r", "requirements.txt"], "pip", relative_path).await;
+    // Each requirements file is installed and reported on its own, one after another since they
+    // share an environment; `run_installs` likewise keeps pip apart from the other Python managers
+    let requirements: Vec<String> = files_matching(path, &args.requirements_glob)
+        .into_iter()
+        .filter(|file| !args.managers.contains_key(file))
+        .collect();
+    for file in &requirements {
+        info!(args, "Detected pip dependencies in {:?}", relative_path.join(file));
+    }
+    if !requirements.is_empty() {
+        let install = async move {
+            let mut success = true;
+            for file in &requirements {
+                let prefix = format!("pip {}", file);
+                success &= run_manager(path, "pip", &["install", "-r", file], &prefix, relative_path, args).await;
+            }
+            success
+        };
+        installs.push(("pip", install.boxed()));
         updated = true;
     }
 
     if !updated {
 
-        println!("No recognized dependency manager found for {:?}", relative_path);
+        info!(args, "No recognized dependency manager found for {:?}", relative_path);
+        return Outcome::Skipped;
+    }
+
+    let results = run_installs(installs, relative_path, args).await;
+    if results.len() > 1 && !args.dry_run {
+        let summary: Vec<String> = results
+            .iter()
+            .map(|(manager, success)| format!("{} {}", manager, if *success { "ok" } else { "failed" }))
+            .collect();
+        let all_succeeded = results.iter().all(|(_, success)| *success);
+        let color = if all_succeeded { Color::Green } else { Color::Red };
+        let mut stdout = StandardStream::stdout(args.color_choice());
+        let message = format!("{}\n", summary.join(", "));
+        print_with_prefix(&mut stdout, "Dependencies", &message, color, relative_path, args.is_quiet()).unwrap();
+    }
+    results.iter().all(|(_, success)| *success).into()
+}
+
+/// A dependency install tagged with its position in detection order and its manager's name
+type Install<'a> = (usize, &'a str, BoxFuture<'a, bool>);
+
+/// Runs a repo's dependency installs, concurrently on as many spare `--jobs` permits as are free
+/// and otherwise one after another, returning each manager's success in the order given
+async fn run_installs<'a>(installs: Vec<(&'a str, BoxFuture<'a, bool>)>, relative_path: &Path, args: &Args) -> Vec<(&'a str, bool)> {
+    // Managers that write to the same place stay together so they never run at the same time
+    let mut groups: Vec<(&str, Vec<Install>)> = Vec::new();
+    for (index, (manager, install)) in installs.into_iter().enumerate() {
+        let group = install_group(manager, args);
+        match groups.iter_mut().find(|(name, _)| *name == group) {
+            Some((_, members)) => members.push((index, manager, install)),
+            None => groups.push((group, vec![(index, manager, install)])),
+        }
+    }
+
+    // Spare permits are only taken if free right now; waiting for them while this repo holds
+    // its own permit could deadlock once every repo is doing the same. A dry run uses a single
+    // lane so the planned commands are printed in detection order
+    let lanes_wanted = if args.dry_run { 1 } else { groups.len() };
+    let spare_permits: Vec<_> = (1..lanes_wanted)
+        .map_while(|_| JOBS.get()?.clone().try_acquire_owned().ok())
+        .collect();
+
+    let mut lanes: Vec<Vec<Install>> = (0..=spare_permits.len()).map(|_| Vec::new()).collect();
+    let lane_count = lanes.len();
+    for (index, (_, members)) in groups.into_iter().enumerate() {
+        lanes[index % lane_count].extend(members);
+    }
+
+    let lanes = lanes.into_iter().map(|lane| async move {
+        let mut results = Vec::new();
+        for (index, manager, install) in lane {
+            let success = install.await;
+            // Once the run is cancelled, the installs still to come are refused before they start
+            fail_fast(success.into(), relative_path, args);
+            results.push((index, manager, success));
+        }
+        results
+    });
+    let mut results: Vec<_> = future::join_all(lanes).await.into_iter().flatten().collect();
+    drop(spare_permits);
+
+    results.sort_by_key(|(index, _, _)| *index);
+    results.into_iter().map(|(_, manager, success)| (manager, success)).collect()
+}
+
+/// Name shared by the installs that must not run concurrently: the Node managers all write
+/// `node_modules` and the Python ones the active environment; any other manager is alone in its group
+fn install_group<'a>(manager: &'a str, args: &Args) -> &'a str {
+    match manager {
+        "Bun" | "npm" | "Yarn" | "pnpm" | "Deno" => "node",
+        "uv" | "Pipenv" | "Poetry" | "pip" | "Conda" => "python",
+        // `[managers]` commands are named after the lockfile they replace
+        "bun.lockb" | "bun.lock" | "package-lock.json" | "yarn.lock" | "pnpm-lock.yaml" | "deno.lock" | "deno.json"
+        | "deno.jsonc" | "package.json" => "node",
+        "uv.lock" | "Pipfile" | "Pipfile.lock" | "poetry.lock" | "pyproject.toml" | "environment.yml" => "python",
+        file if args.requirements_glob.compile_matcher().is_match(file) => "python",
+        other => other,
+    }
+}
+
+/// Checks whether the directory is the root of a pnpm, Yarn or npm workspace
+fn is_js_workspace_root(path: &Path) -> bool {
+    if path.join("pnpm-workspace.yaml").exists() {
+        return true;
+    }
+    std::fs::read_to_string(path.join("package.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .is_some_and(|manifest| manifest.get("workspaces").is_some())
+}
+
+/// Checks whether `path` is one of the packages listed by the JS workspace at `root`, in its
+/// `pnpm-workspace.yaml` or the `workspaces` field of its package.json
+fn is_js_workspace_member(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let patterns = match std::fs::read_to_string(root.join("pnpm-workspace.yaml")) {
+        Ok(contents) => pnpm_workspace_packages(&contents),
+        Err(_) => {
+            let manifest = std::fs::read_to_string(root.join("package.json"))
+                .ok()
+                .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok());
+            // Yarn also accepts `{"packages": [...]}` so it can take other workspace settings alongside
+            let workspaces = manifest.as_ref().and_then(|manifest| {
+                let workspaces = manifest.get("workspaces")?;
+                workspaces.as_array().or_else(|| workspaces.get("packages")?.as_array())
+            });
+            workspaces
+                .into_iter()
+                .flatten()
+                .filter_map(|pattern| pattern.as_str().map(str::to_string))
+                .collect()
+        }
+    };
+    matches_workspace_globs(relative, &patterns)
+}
+
+/// Reads the `packages` list of a `pnpm-workspace.yaml`, in block or flow style
+fn pnpm_workspace_packages(contents: &str) -> Vec<String> {
+    let unquote = |item: &str| item.trim().trim_matches(['\'', '"']).to_string();
+    let mut packages = Vec::new();
+    let mut in_packages = false;
+    for line in contents.lines() {
+        let item = line.split(" #").next().unwrap_or_default().trim();
+        if item.is_empty() || item.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with([' ', '\t', '-']) {
+            let list = item.strip_prefix("packages:").map(str::trim);
+            in_packages = list.is_some();
+            if let Some(list) = list.and_then(|list| list.strip_prefix('[')?.strip_suffix(']')) {
+                packages.extend(list.split(',').map(unquote).filter(|pattern| !pattern.is_empty()));
+            }
+        } else if let (true, Some(pattern)) = (in_packages, item.strip_prefix('-')) {
+            packages.push(unquote(pattern));
+        }
+    }
+    packages
+}
+
+/// Whether a path relative to a workspace root matches one of its member globs and none of the `!` negated ones
+fn matches_workspace_globs(relative: &Path, patterns: &[String]) -> bool {
+    let matches = |pattern: &str| {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .is_ok_and(|glob| glob.compile_matcher().is_match(relative))
+    };
+    let (negated, members): (Vec<&String>, Vec<&String>) = patterns.iter().partition(|pattern| pattern.starts_with('!'));
+    members.iter().any(|pattern| matches(pattern)) && !negated.iter().any(|pattern| matches(&pattern[1..]))
+}
+
+/// Checks whether the directory's `Cargo.toml` declares a `[workspace]`
+fn is_cargo_workspace_root(path: &Path) -> bool {
+    std::fs::read_to_string(path.join("Cargo.toml"))
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Table>(&contents).ok())
+        .is_some_and(|manifest| manifest.contains_key("workspace"))
+}
+
+/// Checks whether `path` matches the `members` globs of the Cargo workspace at `root` and
+/// isn't under one of its `exclude` paths
+fn is_cargo_workspace_member(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let manifest = std::fs::read_to_string(root.join("Cargo.toml"))
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Table>(&contents).ok());
+    let workspace = manifest.as_ref().and_then(|manifest| manifest.get("workspace"));
+    let paths = |key: &str| -> Vec<String> {
+        workspace
+            .and_then(|workspace| workspace.get(key)?.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.as_str().map(str::to_string))
+            .collect()
+    };
+
+    let excluded = paths("exclude").iter().any(|exclude| relative.starts_with(exclude.trim_start_matches("./")));
+    !excluded && matches_workspace_globs(relative, &paths("members"))
+}
+
+/// Which tool a `pyproject.toml` is written for
+enum PyProject {
+    /// Has a `[tool.poetry]` table
+    Poetry,
+    /// Has a standard `[project]` table
+    Pep621,
+}
+
+/// Reads `pyproject.toml` to tell Poetry projects from plain PEP 621 ones; `None` if it is neither or unreadable
+fn pyproject_kind(pyproject: &Path) -> Option<PyProject> {
+    let contents = std::fs::read_to_string(pyproject).ok()?;
+    let manifest: toml::Table = toml::from_str(&contents).ok()?;
+    if manifest.get("tool").and_then(|tool| tool.get("poetry")).is_some() {
+        Some(PyProject::Poetry)
+    } else if manifest.contains_key("project") {
+        Some(PyProject::Pep621)
+    } else {
+        None
+    }
+}
+
+/// Checks whether `pubspec.yaml` declares a `flutter:` section or SDK dependency
+fn is_flutter_project(path: &Path) -> bool {
+    std::fs::read_to_string(path.join("pubspec.yaml"))
+        .map(|pubspec| pubspec.lines().any(|line| line.trim_start().starts_with("flutter:")))
+        .unwrap_or(false)
+}
+
+/// Reads the top-level `name:` of a Conda environment file
+fn conda_env_name(environment_file: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(environment_file).ok()?;
+    contents.lines().find_map(|line| {
+        let name = line.strip_prefix("name:")?.trim().trim_matches(['"', '\'']);
+        (!name.is_empty()).then(|| name.to_string())
+    })
+}
+
+/// Lists the names of files directly inside `path` matching the glob, sorted
+fn files_matching(path: &Path, glob: &Glob) -> Vec<String> {
+    let matcher = glob.compile_matcher();
+    let mut files: Vec<String> = std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| matcher.is_match(name))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Lists the names of files directly inside `path` with the given extension, sorted
+fn files_with_extension(path: &Path, extension: &str) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| file.is_file() && file.extension().is_some_and(|ext| ext == extension))
+        .filter_map(|file| file.file_name()?.to_str().map(str::to_string))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Checks whether an executable with the given name is on the PATH
+fn command_exists(command: &str) -> bool {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&paths).any(|dir| {
+        let candidate = dir.join(command);
+        candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+    })
+}
+
+/// Checks whether a file exists and can be executed
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    {
+        path.is_file()
     }
 }
 
 /// Helper to run a command in a given directory
+async fn run_command(
+    path: &Path,
+    command: &str,
+    args: &[&str],
+    prefix: &str,
+    relative_path: &Path,
+    opts: &Args,
+) -> Result<(), CommandError> {
+    if opts.dry_run {
+        let mut stdout = StandardStream::stdout(opts.color_choice());
+        let message = format!("Would run `{}` in {:?}\n", command_line(command, args, opts), path);
+        print_with_prefix(&mut stdout, prefix, &message, Color::Cyan, relative_path, opts.is_json()).unwrap();
+        return Ok(());
+    }
+
+    let cancel = CANCEL.get().cloned().unwrap_or_default();
+    if cancel.is_cancelled() {
+        let _ = REPO_STATE.try_with(|state| state.cancelled.set(true));
+        return Err(CommandError::Cancelled);
+    }
 
-async fn run_command(path: &Path, command: &str, args: &[&str], prefix: &str, relative_path: &Path) {
-    let mut child = Command::new(command)
+    let child = Command::new(command)
         .args(args)
+        .envs(opts.env.iter().map(|(key, value)| (key, value)))
         .current_dir(path)
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute command");
+        .spawn();
+    let started = Instant::now();
+    let command_line = format!("{} {}", command, args.join(" ")).trim_end().to_string();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            tracing::warn!(repo = %relative_path.display(), command = %command_line, "not found on PATH");
+            error!("{} not found on PATH, skipping in {:?}", command, relative_path);
+            record_error(RepoError::MissingTool);
+            return Err(CommandError::NotFound);
+        }
+        Err(e) => {
+            tracing::error!(repo = %relative_path.display(), command = %command_line, error = %e, "failed to start");
+            error!("Failed to start {} in {:?}: {}", command, relative_path, e);
+            record_error(RepoError::Io);
+            return Err(CommandError::Failed(String::new()));
+        }
+    };
 
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    let mut stderr = StandardStream::stderr(ColorChoice::Always);
+    // Output is collected per command and added to the repo's held back block, so concurrent
+    // repos never interleave; `--stream` flushes every line instead
+    let stdout_writer = BufferWriter::stdout(opts.color_choice());
+    let stderr_writer = BufferWriter::stderr(opts.color_choice());
 
-    if let Some(stdout_handle) = child.stdout.take() {
+    let stdout_task = child.stdout.take().map(|stdout_handle| {
         let prefix = prefix.to_string();
+        let quiet = opts.is_quiet();
+        let stream = opts.stream;
 
 
-        let relative_path = relative_path.to_path_buf();
+        let label = repo_label(relative_path);
         tokio::spawn(async move {
             let mut reader = tokio::io::BufReader::new(stdout_handle);
-            let mut line = String::new();
-
+            let mut line = Vec::new();
+            let mut buffer = stdout_writer.buffer();
 
-            while tokio::io::AsyncBufReadExt::read_line(&mut reader, &mut line).await.unwrap() > 0 {
-                print_with_prefix(&mut stdout, &prefix, &line, Color::Green, &relative_path).unwrap();
+            // Read raw bytes so output that isn't valid UTF-8 is shown lossily instead of aborting
+            while matches!(tokio::io::AsyncBufReadExt::read_until(&mut reader, b'\n', &mut line).await, Ok(n) if n > 0) {
+                let text = String::from_utf8_lossy(&line);
+                write_with_prefix(&mut buffer, &prefix, &text, Color::Green, &label, quiet).unwrap();
+                if stream {
+                    suspend_progress(|| stdout_writer.print(&buffer)).unwrap();
+                    buffer.clear();
+                }
                 line.clear();
             }
-        });
-    }
+            buffer
+        })
+    });
 
-    if let Some(stderr_handle) = child.stderr.take() {
+    let stderr_task = child.stderr.take().map(|stderr_handle| {
         let prefix = prefix.to_string();
+        let stream = opts.stream;
 
 
-        let relative_path = relative_path.to_path_buf();
+        let label = repo_label(relative_path);
         tokio::spawn(async move {
             let mut reader = tokio::io::BufReader::new(stderr_handle);
-            let mut line = String::new();
+            let mut line = Vec::new();
+            let mut buffer = stderr_writer.buffer();
+            let mut captured = String::new();
 
 
-            while tokio::io::AsyncBufReadExt::read_line(&mut reader, &mut line).await.unwrap() > 0 {
-                print_with_prefix(&mut stderr, &prefix, &line, Color::Red, &relative_path).unwrap();
+            while matches!(tokio::io::AsyncBufReadExt::read_until(&mut reader, b'\n', &mut line).await, Ok(n) if n > 0) {
+                let text = String::from_utf8_lossy(&line);
+                write_with_prefix(&mut buffer, &prefix, &text, Color::Red, &label, false).unwrap();
+                captured.push_str(&text);
+                if stream {
+                    suspend_progress(|| stderr_writer.print(&buffer)).unwrap();
+                    buffer.clear();
+                }
                 line.clear();
             }
-        });
+            (buffer, captured)
+        })
+    });
+
+
+    let waited = async {
+        match opts.timeout {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), child.wait())
+                .await
+                .map_err(|_| CommandError::TimedOut),
+            None => Ok(child.wait().await),
+        }
+    };
+    let stopped = tokio::select! {
+        waited = waited => waited,
+        () = cancel.cancelled() => Err(CommandError::Cancelled),
+    };
+    let status = match stopped {
+        Ok(status) => status,
+        Err(reason) => {
+            // `kill` also reaps the child; the readers are aborted rather than drained because
+            // a grandchild (e.g. ssh spawned by git) may still hold the pipes open
+            let _ = child.kill().await;
+            if let Some(task) = stdout_task {
+                task.abort();
+            }
+            if let Some(task) = stderr_task {
+                task.abort();
+            }
+            if let CommandError::TimedOut = reason {
+                tracing::error!(
+                    repo = %relative_path.display(),
+                    command = %command_line,
+                    duration_secs = started.elapsed().as_secs_f64(),
+                    "timed out"
+                );
+                let secs = opts.timeout.unwrap_or_default();
+                error!("Timed out after {}s running {} in {:?}, killed it", secs, command, relative_path);
+                let _ = REPO_STATE.try_with(|state| state.timed_out.set(true));
+                record_error(RepoError::TimedOut);
+            } else {
+                tracing::warn!(repo = %relative_path.display(), command = %command_line, "cancelled");
+                error!("Cancelled {} in {:?} because another repo failed", command, relative_path);
+                let _ = REPO_STATE.try_with(|state| state.cancelled.set(true));
+            }
+            return Err(reason);
+        }
+    };
+
+    // Drain the readers so a repo's output is finished before its permit is released
+    if let Some(task) = stdout_task {
+        if let Ok(buffer) = task.await {
+            flush_output(&buffer, false, opts.color_choice());
+        }
+    }
+    let mut stderr_output = String::new();
+    if let Some(task) = stderr_task {
+        if let Ok((buffer, captured)) = task.await {
+            flush_output(&buffer, true, opts.color_choice());
+            stderr_output = captured;
+        }
     }
 
+    if let Ok(status) = &status {
+        tracing::info!(
+            repo = %relative_path.display(),
+            command = %command_line,
+            exit_code = status.code(),
+            duration_secs = started.elapsed().as_secs_f64(),
+            "command finished"
+        );
+    }
 
-    let status = child.wait().await.expect("Failed to wait on child process");
+    match status {
+        Ok(status) if status.success() => {
 
-    if !status.success() {
+            info!(opts, "Successfully ran {} in {:?}", command, relative_path);
+            Ok(())
+        }
+        Ok(status) => {
 
-        eprintln!("Failed to run {} in {:?}", command, relative_path);
-    } else {
+            error!("Failed to run {} in {:?}", command, relative_path);
+            record_error(RepoError::CommandFailed);
+            if let Some(code) = status.code() {
+                let _ = REPO_STATE.try_with(|state| {
+                    if state.exit_code.get().is_none() {
+                        state.exit_code.set(Some(code));
+                    }
+                });
+            }
+            Err(CommandError::Failed(stderr_output))
+        }
+        Err(e) => {
+            error!("Failed to wait on {} in {:?}: {}", command, relative_path, e);
+            record_error(RepoError::Io);
+            Err(CommandError::Failed(stderr_output))
+        }
+    }
+}
+
+/// Runs a dependency manager command; a manager that isn't installed is skipped rather than failed
+async fn run_manager(path: &Path, command: &str, args: &[&str], prefix: &str, relative_path: &Path, opts: &Args) -> bool {
+    matches!(
+        run_command(path, command, args, prefix, relative_path, opts).await,
+        Ok(()) | Err(CommandError::NotFound)
+    )
+}
 
-        println!("Successfully ran {} in {:?}", command, relative_path);
+
+/// Writes a message tagged with the repo path and prefix; messages marked `quiet` are dropped
+fn print_with_prefix(stream: &mut StandardStream, prefix: &str, message: &str, color: Color, relative_path: &Path, quiet: bool) -> io::Result<()> {
+    let label = repo_label(relative_path);
+    if let Some(result) = capture_output(|output| write_with_prefix(output.target(false), prefix, message, color, &label, quiet)) {
+        return result;
     }
+    suspend_progress(|| write_with_prefix(stream, prefix, message, color, &label, quiet))
 }
 
+/// Like `print_with_prefix`, but for any writer, such as the per-command output buffers
+fn write_with_prefix(stream: &mut impl WriteColor, prefix: &str, message: &str, color: Color, label: &str, quiet: bool) -> io::Result<()> {
+    if quiet {
+        return Ok(());
+    }
 
-fn print_with_prefix(stream: &mut StandardStream, prefix: &str, message: &str, color: Color, relative_path: &Path) -> io::Result<()> {
     stream.set_color(ColorSpec::new().set_fg(Some(color)))?;
 
-    write!(stream, "[{}][{}] ", relative_path.display(), prefix)?;
+    write!(stream, "[{}][{}] ", label, prefix)?;
     stream.reset()?;
     write!(stream, "{}", message)?;
     stream.flush()
 }
+
+/// Repo tag for output lines, `path@branch` once the repo's branch has been resolved
+fn repo_label(relative_path: &Path) -> String {
+    let branch = REPO_STATE.try_with(|state| state.branch.borrow().clone()).ok().flatten();
+    match branch {
+        Some(branch) => format!("{}@{}", relative_path.display(), branch),
+        None => relative_path.display().to_string(),
+    }
+}
+
+/// Runs `f` with the progress bar cleared so its output doesn't interleave with the bar
+fn suspend_progress<R>(f: impl FnOnce() -> R) -> R {
+    match PROGRESS.get() {
+        Some(progress) => progress.suspend(f),
+        None => f(),
+    }
+}
+
+/// Prints a line to stdout or stderr, or holds it back with the repo's output unless `--stream`
+fn print_line(line: &str, to_stderr: bool) {
+    let held = capture_output(|output| {
+        let _ = writeln!(output.target(to_stderr), "{}", line);
+    });
+    if held.is_none() {
+        if to_stderr {
+            suspend_progress(|| eprintln!("{}", line));
+        } else {
+            suspend_progress(|| println!("{}", line));
+        }
+    }
+}
+
+/// Prints a finished command's output, or holds it back with the repo's output unless `--stream`
+fn flush_output(buffer: &Buffer, to_stderr: bool, color: ColorChoice) {
+    let held = capture_output(|output| {
+        let _ = output.target(to_stderr).write_all(buffer.as_slice());
+    });
+    if held.is_none() {
+        let writer = if to_stderr { BufferWriter::stderr(color) } else { BufferWriter::stdout(color) };
+        let _ = suspend_progress(|| writer.print(buffer));
+    }
+}
+
+/// Runs `f` on the current repo's held back output, or returns `None` if output isn't being held
+fn capture_output<R>(f: impl FnOnce(&mut CapturedOutput) -> R) -> Option<R> {
+    REPO_STATE
+        .try_with(|state| state.output.borrow_mut().as_mut().map(f))
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(vulnerabilities: &Vulnerabilities) -> [u64; 4] {
+        [vulnerabilities.low, vulnerabilities.moderate, vulnerabilities.high, vulnerabilities.critical]
+    }
+
+    #[test]
+    fn parses_npm_audit_report() {
+        let report = r#"{
+  "auditReportVersion": 2,
+  "vulnerabilities": {},
+  "metadata": {
+    "vulnerabilities": {"info": 0, "low": 1, "moderate": 2, "high": 0, "critical": 3, "total": 6}
+  }
+}"#;
+        let vulnerabilities = Vulnerabilities::parse(report).unwrap();
+        assert_eq!(counts(&vulnerabilities), [1, 2, 0, 3]);
+        assert_eq!(vulnerabilities.total(), 6);
+    }
+
+    #[test]
+    fn rejects_json_report_without_metadata() {
+        assert!(Vulnerabilities::parse(r#"{"error": {"code": "ENOLOCK"}}"#).is_none());
+    }
+
+    #[test]
+    fn parses_yarn_audit_summary() {
+        let report = concat!(
+            r#"{"type":"auditAdvisory","data":{"advisory":{"severity":"high"}}}"#,
+            "\n",
+            r#"{"type":"auditSummary","data":{"vulnerabilities":{"info":0,"low":0,"moderate":1,"high":1,"critical":0}}}"#,
+            "\n",
+        );
+        assert_eq!(counts(&Vulnerabilities::parse(report).unwrap()), [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn counts_yarn_berry_advisories_by_severity() {
+        let report = concat!(
+            r#"{"value":"lodash","children":{"ID":1,"Severity":"critical"}}"#,
+            "\n",
+            r#"{"value":"minimist","children":{"ID":2,"Severity":"Moderate"}}"#,
+            "\n",
+            r#"{"value":"semver","children":{"ID":3,"Severity":"moderate"}}"#,
+            "\n",
+        );
+        assert_eq!(counts(&Vulnerabilities::parse(report).unwrap()), [0, 2, 0, 1]);
+    }
+
+    #[test]
+    fn rejects_report_with_no_json() {
+        assert!(Vulnerabilities::parse("error Couldn't find a lockfile\n").is_none());
+    }
+
+    #[test]
+    fn include_does_not_walk_into_repos_it_rejects() {
+        let base = std::env::temp_dir().join(format!("mpr-include-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        for repo in ["web", "web/api", "services/api"] {
+            Repository::init(base.join(repo)).unwrap();
+        }
+
+        let args = Args::parse_from(["mpr", "--include", "**/api"]);
+        let found: Vec<PathBuf> = discover_repositories(&base, &args).into_iter().map(|(_, relative, _)| relative).collect();
+        let _ = std::fs::remove_dir_all(&base);
+        assert_eq!(found, [PathBuf::from("services/api")]);
+    }
+
+    #[test]
+    fn commit_all_refuses_a_conflicted_merge() {
+        let dir = std::env::temp_dir().join(format!("mpr-commit-conflict-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "mpr").unwrap();
+        config.set_str("user.email", "mpr@example.com").unwrap();
+        let signature = repo.signature().unwrap();
+        let commit = |update_ref: &str, contents: &str, parents: &[&git2::Commit]| {
+            let blob = repo.blob(contents.as_bytes()).unwrap();
+            let mut tree = repo.treebuilder(None).unwrap();
+            tree.insert("file.txt", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+            let oid = repo.commit(Some(update_ref), &signature, &signature, contents, &tree, parents).unwrap();
+            repo.find_commit(oid).unwrap()
+        };
+        let base = commit("HEAD", "base\n", &[]);
+        let theirs = commit("refs/heads/theirs", "theirs\n", &[&base]);
+        let ours = commit("HEAD", "ours\n", &[&base]);
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force())).unwrap();
+        repo.merge(&[&repo.find_annotated_commit(theirs.id()).unwrap()], None, None).unwrap();
+        assert!(repo.index().unwrap().has_conflicts());
+
+        let committed = commit_all(&dir, "resolve");
+        let head = repo.head().unwrap().target();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(committed.is_err());
+        assert_eq!(head, Some(ours.id()));
+    }
+
+    #[test]
+    fn reads_pnpm_workspace_packages() {
+        let block = "packages:\n  # apps first\n  - 'apps/*'\n  - \"packages/**\"\n  - '!**/test/**'\ncatalog:\n  - react\n";
+        assert_eq!(pnpm_workspace_packages(block), ["apps/*", "packages/**", "!**/test/**"]);
+        assert_eq!(pnpm_workspace_packages("packages: ['apps/*', libs/core]\n"), ["apps/*", "libs/core"]);
+    }
+
+    #[test]
+    fn matches_workspace_member_globs() {
+        let patterns = ["./apps/*".to_string(), "libs/**".to_string(), "!libs/legacy".to_string()];
+        assert!(matches_workspace_globs(Path::new("apps/web"), &patterns));
+        assert!(matches_workspace_globs(Path::new("libs/ui/button"), &patterns));
+        assert!(!matches_workspace_globs(Path::new("apps/web/nested"), &patterns));
+        assert!(!matches_workspace_globs(Path::new("libs/legacy"), &patterns));
+        assert!(!matches_workspace_globs(Path::new("tools/cli"), &patterns));
+    }
+}
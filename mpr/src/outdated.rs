@@ -0,0 +1,301 @@
+//! Parsing of the "outdated dependencies" reports printed by each package manager
+
+use serde_json::Value;
+
+/// A dependency with a newer release than the one in use
+pub struct Package {
+    pub name: String,
+    pub current: String,
+    pub latest: String,
+}
+
+/// Report format of the tool that produced the output
+#[derive(Clone, Copy)]
+pub enum Report {
+    /// `cargo outdated --format json`
+    Cargo,
+    /// `npm outdated --json` and `pnpm outdated --format json`, keyed by package name
+    Npm,
+    /// `yarn outdated --json`, newline delimited with the rows in a `table` record
+    Yarn,
+    /// `pip list --outdated --format json`
+    Pip,
+    /// `go list -u -m -json all`, a stream of module objects
+    Go,
+    /// `composer outdated --direct --format json`
+    Composer,
+    /// `bundle outdated --parseable`, lines like `rack (newest 3.0.8, installed 2.2.7)`
+    Bundler,
+    /// `dotnet list package --outdated`, a text table with `>` before each package
+    Dotnet,
+}
+
+impl Report {
+    /// Extracts the outdated packages, or `None` if the output isn't in the expected format
+    pub fn parse(self, output: &str) -> Option<Vec<Package>> {
+        // Most tools print nothing at all when everything is current
+        if output.trim().is_empty() {
+            return Some(Vec::new());
+        }
+
+        match self {
+            Report::Cargo => parse_cargo(output),
+            Report::Npm => parse_npm(output),
+            Report::Yarn => parse_yarn(output),
+            Report::Pip => parse_pip(output),
+            Report::Go => parse_go(output),
+            Report::Composer => parse_composer(output),
+            Report::Bundler => Some(parse_bundler(output)),
+            Report::Dotnet => Some(parse_dotnet(output)),
+        }
+    }
+}
+
+fn package(name: &str, current: &Value, latest: &Value) -> Package {
+    Package {
+        name: name.to_string(),
+        current: current.as_str().unwrap_or("?").to_string(),
+        latest: latest.as_str().unwrap_or("?").to_string(),
+    }
+}
+
+fn parse_cargo(output: &str) -> Option<Vec<Package>> {
+    let report: Value = serde_json::from_str(output).ok()?;
+    let dependencies = report.get("dependencies")?.as_array()?;
+    Some(
+        dependencies
+            .iter()
+            .filter_map(|dependency| {
+                let name = dependency.get("name")?.as_str()?;
+                Some(package(name, &dependency["project"], &dependency["latest"]))
+            })
+            .collect(),
+    )
+}
+
+fn parse_npm(output: &str) -> Option<Vec<Package>> {
+    let report: Value = serde_json::from_str(output).ok()?;
+    Some(
+        report
+            .as_object()?
+            .iter()
+            .map(|(name, versions)| package(name, &versions["current"], &versions["latest"]))
+            .collect(),
+    )
+}
+
+fn parse_yarn(output: &str) -> Option<Vec<Package>> {
+    let table = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .find(|record| record["type"] == "table")?;
+    let rows = table.pointer("/data/body")?.as_array()?;
+    Some(
+        rows.iter()
+            .filter_map(|row| {
+                let name = row.get(0)?.as_str()?;
+                Some(package(name, &row[1], &row[3]))
+            })
+            .collect(),
+    )
+}
+
+fn parse_pip(output: &str) -> Option<Vec<Package>> {
+    let report: Value = serde_json::from_str(output).ok()?;
+    Some(
+        report
+            .as_array()?
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.get("name")?.as_str()?;
+                Some(package(name, &entry["version"], &entry["latest_version"]))
+            })
+            .collect(),
+    )
+}
+
+fn parse_go(output: &str) -> Option<Vec<Package>> {
+    let mut packages = Vec::new();
+    for module in serde_json::Deserializer::from_str(output).into_iter::<Value>() {
+        let module = module.ok()?;
+        // Only direct requirements are worth reporting; indirect ones follow them
+        if module["Main"] == true || module["Indirect"] == true {
+            continue;
+        }
+        if let (Some(name), Some(update)) = (module["Path"].as_str(), module.get("Update")) {
+            packages.push(package(name, &module["Version"], &update["Version"]));
+        }
+    }
+    Some(packages)
+}
+
+fn parse_composer(output: &str) -> Option<Vec<Package>> {
+    let report: Value = serde_json::from_str(output).ok()?;
+    let installed = report.get("installed")?.as_array()?;
+    Some(
+        installed
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.get("name")?.as_str()?;
+                Some(package(name, &entry["version"], &entry["latest"]))
+            })
+            .collect(),
+    )
+}
+
+fn parse_bundler(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, details) = line.trim().split_once(" (")?;
+            let details = details.strip_suffix(')')?;
+            let mut latest = "?";
+            let mut current = "?";
+            for part in details.split(", ") {
+                if let Some(version) = part.strip_prefix("newest ") {
+                    latest = version;
+                } else if let Some(version) = part.strip_prefix("installed ") {
+                    current = version;
+                }
+            }
+            Some(Package {
+                name: name.to_string(),
+                current: current.to_string(),
+                latest: latest.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn parse_dotnet(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.trim().strip_prefix('>')?.split_whitespace();
+            let name = columns.next()?;
+            let _requested = columns.next()?;
+            let resolved = columns.next()?;
+            let latest = columns.next()?;
+            Some(Package {
+                name: name.to_string(),
+                current: resolved.to_string(),
+                latest: latest.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(packages: Option<Vec<Package>>) -> Vec<(String, String, String)> {
+        packages
+            .expect("report should parse")
+            .into_iter()
+            .map(|package| (package.name, package.current, package.latest))
+            .collect()
+    }
+
+    fn row(name: &str, current: &str, latest: &str) -> (String, String, String) {
+        (name.to_string(), current.to_string(), latest.to_string())
+    }
+
+    #[test]
+    fn empty_output_means_nothing_outdated() {
+        assert!(Report::Cargo.parse("  \n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_cargo() {
+        let output = r#"{"crate_name":"mpr","dependencies":[
+            {"name":"serde","project":"1.0.100","compat":"1.0.190","latest":"1.0.190","kind":"Normal","platform":null},
+            {"name":"clap","project":"3.2.0","compat":"---","latest":"4.4.7","kind":"Normal","platform":null}
+        ]}"#;
+        assert_eq!(summary(Report::Cargo.parse(output)), [row("serde", "1.0.100", "1.0.190"), row("clap", "3.2.0", "4.4.7")]);
+    }
+
+    #[test]
+    fn parses_npm() {
+        let output = r#"{
+  "lodash": {"current": "4.17.20", "wanted": "4.17.21", "latest": "4.17.21", "dependent": "app", "location": "node_modules/lodash"}
+}"#;
+        assert_eq!(summary(Report::Npm.parse(output)), [row("lodash", "4.17.20", "4.17.21")]);
+    }
+
+    #[test]
+    fn parses_yarn() {
+        let output = concat!(
+            r#"{"type":"info","data":"Color legend : ..."}"#,
+            "\n",
+            r#"{"type":"table","data":{"head":["Package","Current","Wanted","Latest","Package Type","URL"],"body":[["react","17.0.2","17.0.2","18.2.0","dependencies","https://reactjs.org/"]]}}"#,
+            "\n",
+        );
+        assert_eq!(summary(Report::Yarn.parse(output)), [row("react", "17.0.2", "18.2.0")]);
+    }
+
+    #[test]
+    fn parses_pip() {
+        let output = r#"[{"name": "requests", "version": "2.25.1", "latest_version": "2.31.0", "latest_filetype": "wheel"}]"#;
+        assert_eq!(summary(Report::Pip.parse(output)), [row("requests", "2.25.1", "2.31.0")]);
+    }
+
+    #[test]
+    fn parses_go_skipping_main_and_indirect_modules() {
+        let output = r#"{
+	"Path": "example.com/app",
+	"Main": true
+}
+{
+	"Path": "golang.org/x/text",
+	"Version": "v0.3.7",
+	"Update": {"Path": "golang.org/x/text", "Version": "v0.14.0"},
+	"Indirect": true
+}
+{
+	"Path": "github.com/spf13/cobra",
+	"Version": "v1.6.0",
+	"Update": {"Path": "github.com/spf13/cobra", "Version": "v1.8.0"}
+}
+{
+	"Path": "github.com/google/uuid",
+	"Version": "v1.4.0"
+}
+"#;
+        assert_eq!(summary(Report::Go.parse(output)), [row("github.com/spf13/cobra", "v1.6.0", "v1.8.0")]);
+    }
+
+    #[test]
+    fn parses_composer() {
+        let output = r#"{"installed": [{"name": "monolog/monolog", "direct-dependency": true, "version": "2.9.1", "latest": "3.5.0", "latest-status": "update-possible"}]}"#;
+        assert_eq!(summary(Report::Composer.parse(output)), [row("monolog/monolog", "2.9.1", "3.5.0")]);
+    }
+
+    #[test]
+    fn parses_bundler() {
+        let output = "rack (newest 3.0.8, installed 2.2.7)\nrails (newest 7.1.2, installed 7.0.4, requested ~> 7.0)\n";
+        assert_eq!(summary(Report::Bundler.parse(output)), [row("rack", "2.2.7", "3.0.8"), row("rails", "7.0.4", "7.1.2")]);
+    }
+
+    #[test]
+    fn parses_dotnet() {
+        let output = "\
+Project `App` has the following updates to its packages
+   [net8.0]:
+   Top-level Package      Requested   Resolved   Latest
+   > Newtonsoft.Json      12.0.1      12.0.1     13.0.3
+   > Serilog              2.10.0      2.10.0     3.1.1
+";
+        assert_eq!(
+            summary(Report::Dotnet.parse(output)),
+            [row("Newtonsoft.Json", "12.0.1", "13.0.3"), row("Serilog", "2.10.0", "3.1.1")]
+        );
+    }
+
+    #[test]
+    fn rejects_output_in_another_format() {
+        assert!(Report::Cargo.parse("error: no such command: `outdated`").is_none());
+        assert!(Report::Npm.parse("[1, 2]").is_none());
+        assert!(Report::Yarn.parse(r#"{"type":"info","data":"no table"}"#).is_none());
+    }
+}